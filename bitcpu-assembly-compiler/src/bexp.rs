@@ -0,0 +1,492 @@
+// Boolean expression language --> bit CPU assembly
+//
+// NAND_CPU                      # first line: NAND_CPU or NOR_CPU
+// a = stdin; b = stdin          # statements: newline or ';'
+// cin = 0
+// sum = a ^ b ^ cin             # ! or ~: not, &: and, ^: xor, |: or
+// cout = (a & b) | (cin & (a ^ b))
+// stdout = sum
+// if cout { stdout = 1 } else { stdout = 0 }
+// while a { a = stdin }
+//
+// The variables get RAM bits from 0x00, the temporary bits follow them.
+// The output is an assembly source for the assembler passes.
+
+use std::collections::HashMap;
+
+const IO: u8 = 0xfd;
+const LOW: u8 = 0xfe;
+const HIGH: u8 = 0xff;
+const RAM_END: u8 = 0xfc; // 0xfc: call/ret in bitcpu-call
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Num(bool),
+    Sym(char),
+    Sep, // newline or ';'
+}
+
+enum Expr {
+    Const(bool),
+    Var(String),
+    Stdin,
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Xor(Box<Expr>, Box<Expr>),
+}
+
+enum Stmt {
+    Assign(String, Expr), // target: variable or stdout
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+}
+
+fn syntax_error(linenum: usize, msg: &str) -> ! {
+    eprintln!("Syntax error in line {linenum} ({msg})");
+    std::process::exit(1);
+}
+
+fn tokenizer(src: &str) -> Vec<(Token, usize)> {
+    let mut tokens = vec![];
+    for (i, line) in src.lines().enumerate().skip(1) {
+        let linenum = i + 1;
+        let mut chars = line.split('#').next().unwrap().chars().peekable();
+        while let Some(ch) = chars.next() {
+            match ch {
+                ' ' | '\t' => (),
+                ';' => tokens.push((Token::Sep, linenum)),
+                '=' | '{' | '}' | '(' | ')' | '!' | '~' | '&' | '|' | '^' => {
+                    tokens.push((Token::Sym(ch), linenum))
+                }
+                '0' | '1' => tokens.push((Token::Num(ch == '1'), linenum)),
+                _ if ch.is_ascii_alphabetic() || ch == '_' => {
+                    let mut ident = String::from(ch);
+                    while let Some(&c) = chars.peek() {
+                        if !c.is_ascii_alphanumeric() && c != '_' {
+                            break;
+                        }
+                        ident.push(c);
+                        chars.next();
+                    }
+                    tokens.push((Token::Ident(ident.to_lowercase()), linenum));
+                }
+                _ => syntax_error(linenum, &format!("unknown character '{ch}'")),
+            }
+        }
+        tokens.push((Token::Sep, linenum));
+    }
+    tokens
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn linenum(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map_or(1, |&(_, l)| l)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        self.pos += 1;
+        self.tokens.get(self.pos - 1).map(|(t, _)| t.clone())
+    }
+
+    fn expect(&mut self, ch: char) {
+        if self.next() != Some(Token::Sym(ch)) {
+            syntax_error(self.linenum(), &format!("'{ch}' expected"));
+        }
+    }
+
+    fn skip_sep(&mut self) {
+        while self.peek() == Some(&Token::Sep) {
+            self.pos += 1;
+        }
+    }
+
+    // statements until '}' (block) or end of the source
+    fn statements(&mut self, block: bool) -> Vec<Stmt> {
+        let mut stmts = vec![];
+        loop {
+            self.skip_sep();
+            match self.peek() {
+                None if block => syntax_error(self.linenum(), "'}' expected"),
+                None => return stmts,
+                Some(Token::Sym('}')) if block => {
+                    self.pos += 1;
+                    return stmts;
+                }
+                _ => stmts.push(self.statement()),
+            }
+        }
+    }
+
+    fn block(&mut self) -> Vec<Stmt> {
+        self.expect('{');
+        self.statements(true)
+    }
+
+    fn statement(&mut self) -> Stmt {
+        let linenum = self.linenum();
+        match self.next() {
+            Some(Token::Ident(kw)) if kw == "if" => {
+                let cond = self.expr();
+                let then = self.block();
+                let mut otherwise = vec![];
+                if self.peek() == Some(&Token::Ident("else".to_string())) {
+                    self.pos += 1;
+                    if self.peek() == Some(&Token::Ident("if".to_string())) {
+                        otherwise.push(self.statement());
+                    } else {
+                        otherwise = self.block();
+                    }
+                }
+                Stmt::If(cond, then, otherwise)
+            }
+            Some(Token::Ident(kw)) if kw == "while" => {
+                let cond = self.expr();
+                Stmt::While(cond, self.block())
+            }
+            Some(Token::Ident(name)) => {
+                if ["else", "stdin"].contains(&name.as_str()) {
+                    syntax_error(linenum, &format!("'{name}' is not assignable"));
+                }
+                self.expect('=');
+                Stmt::Assign(name, self.expr())
+            }
+            _ => syntax_error(linenum, "statement expected"),
+        }
+    }
+
+    // precedence: ! > & > ^ > |
+    fn expr(&mut self) -> Expr {
+        let mut lhs = self.xor();
+        while self.peek() == Some(&Token::Sym('|')) {
+            self.pos += 1;
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.xor()));
+        }
+        lhs
+    }
+
+    fn xor(&mut self) -> Expr {
+        let mut lhs = self.and();
+        while self.peek() == Some(&Token::Sym('^')) {
+            self.pos += 1;
+            lhs = Expr::Xor(Box::new(lhs), Box::new(self.and()));
+        }
+        lhs
+    }
+
+    fn and(&mut self) -> Expr {
+        let mut lhs = self.unary();
+        while self.peek() == Some(&Token::Sym('&')) {
+            self.pos += 1;
+            lhs = Expr::And(Box::new(lhs), Box::new(self.unary()));
+        }
+        lhs
+    }
+
+    fn unary(&mut self) -> Expr {
+        let linenum = self.linenum();
+        match self.next() {
+            Some(Token::Sym('!' | '~')) => Expr::Not(Box::new(self.unary())),
+            Some(Token::Sym('(')) => {
+                let e = self.expr();
+                self.expect(')');
+                e
+            }
+            Some(Token::Num(b)) => Expr::Const(b),
+            Some(Token::Ident(name)) if name == "stdin" => Expr::Stdin,
+            Some(Token::Ident(name)) if name == "stdout" => {
+                syntax_error(linenum, "stdout is not readable")
+            }
+            Some(Token::Ident(name)) if !["if", "else", "while"].contains(&name.as_str()) => {
+                Expr::Var(name)
+            }
+            _ => syntax_error(linenum, "expression expected"),
+        }
+    }
+}
+
+// value of the operand: mem[addr] ^ inv
+#[derive(Clone, Copy)]
+struct Operand {
+    addr: u8,
+    inv: bool,
+}
+
+struct Codegen {
+    nor: bool, // NOR_CPU, else NAND_CPU
+    vars: HashMap<String, u8>,
+    temp_base: u8,
+    temp_next: u8,
+    temp_max: u8,
+    label_ct: usize,
+    code: Vec<String>,
+}
+
+impl Codegen {
+    fn gate_name(&self) -> &'static str {
+        if self.nor {
+            "nor"
+        } else {
+            "nand"
+        }
+    }
+
+    // the neutral input: nand(HIGH, x) = nor(LOW, x) = !x
+    fn neutral(&self) -> u8 {
+        if self.nor {
+            LOW
+        } else {
+            HIGH
+        }
+    }
+
+    fn gate(&mut self, dst: u8, a: u8, b: u8) {
+        let g = self.gate_name();
+        self.code
+            .push(format!("    0x{dst:02x} = {g}(0x{a:02x}, 0x{b:02x})"));
+    }
+
+    fn temp(&mut self) -> u8 {
+        let t = self.temp_base as usize + self.temp_next as usize;
+        if t >= RAM_END as usize {
+            eprintln!("Out of RAM bits (variables + temporary)");
+            std::process::exit(1);
+        }
+        self.temp_next += 1;
+        self.temp_max = self.temp_max.max(self.temp_next);
+        t as u8
+    }
+
+    fn new_gate(&mut self, a: u8, b: u8) -> u8 {
+        let t = self.temp();
+        self.gate(t, a, b);
+        t
+    }
+
+    // address of a bit with the value of the operand (want_inv: inverted)
+    fn materialize(&mut self, op: Operand, want_inv: bool) -> u8 {
+        if op.inv == want_inv {
+            op.addr
+        } else if op.addr == HIGH || op.addr == LOW {
+            op.addr ^ 1 // HIGH <--> LOW
+        } else {
+            self.new_gate(op.addr, op.addr)
+        }
+    }
+
+    fn expr(&mut self, e: &Expr) -> Operand {
+        match e {
+            Expr::Const(b) => Operand {
+                addr: if *b { HIGH } else { LOW },
+                inv: false,
+            },
+            Expr::Var(name) => Operand {
+                addr: self.vars[name],
+                inv: false,
+            },
+            Expr::Stdin => {
+                // copy: every read of 0xfd is a new input bit
+                let addr = self.new_gate(self.neutral(), IO);
+                Operand { addr, inv: true }
+            }
+            Expr::Not(a) => {
+                let op = self.expr(a);
+                Operand {
+                    addr: op.addr,
+                    inv: !op.inv,
+                }
+            }
+            // nand(a, b) = !(a & b), nor(!a, !b) = a & b
+            Expr::And(a, b) => {
+                let (a, b) = (self.expr(a), self.expr(b));
+                let (a, b) = (self.materialize(a, self.nor), self.materialize(b, self.nor));
+                let addr = self.new_gate(a, b);
+                Operand {
+                    addr,
+                    inv: !self.nor,
+                }
+            }
+            // nand(!a, !b) = a | b, nor(a, b) = !(a | b)
+            Expr::Or(a, b) => {
+                let (a, b) = (self.expr(a), self.expr(b));
+                let (a, b) = (
+                    self.materialize(a, !self.nor),
+                    self.materialize(b, !self.nor),
+                );
+                let addr = self.new_gate(a, b);
+                Operand {
+                    addr,
+                    inv: self.nor,
+                }
+            }
+            // 4 gates: xor with nand, xnor with nor
+            Expr::Xor(a, b) => {
+                let (a, b) = (self.expr(a), self.expr(b));
+                let t = self.new_gate(a.addr, b.addr);
+                let u = self.new_gate(a.addr, t);
+                let v = self.new_gate(b.addr, t);
+                let addr = self.new_gate(u, v);
+                Operand {
+                    addr,
+                    inv: a.inv ^ b.inv ^ self.nor,
+                }
+            }
+        }
+    }
+
+    fn label(&mut self, name: &str) -> String {
+        self.label_ct += 1;
+        format!("{name}_{}", self.label_ct)
+    }
+
+    // skip the next instruction (a jmp), if the condition is true
+    fn skip_if(&mut self, cond: &Expr) {
+        let op = self.expr(cond);
+        let inv = self.materialize(op, true);
+        let (g, n) = (self.gate_name(), self.neutral());
+        self.code
+            .push(format!("    skip_{g}(0x{n:02x}, 0x{inv:02x})"));
+        self.temp_next = 0;
+    }
+
+    fn statements(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            match stmt {
+                Stmt::Assign(target, e) => {
+                    let op = self.expr(e);
+                    let inv = self.materialize(op, true);
+                    let dst = if target == "stdout" {
+                        IO
+                    } else {
+                        self.vars[target]
+                    };
+                    self.gate(dst, self.neutral(), inv);
+                    self.temp_next = 0;
+                }
+                Stmt::If(cond, then, otherwise) => {
+                    let (l_else, l_end) = (self.label("if_else"), self.label("if_end"));
+                    self.skip_if(cond);
+                    self.code.push(format!("    jmp {l_else}"));
+                    self.statements(then);
+                    self.code.push(format!("    jmp {l_end}"));
+                    self.code.push(format!("{l_else}:"));
+                    self.statements(otherwise);
+                    self.code.push(format!("{l_end}:"));
+                }
+                Stmt::While(cond, body) => {
+                    let (l_loop, l_end) = (self.label("while"), self.label("while_end"));
+                    self.code.push(format!("{l_loop}:"));
+                    self.skip_if(cond);
+                    self.code.push(format!("    jmp {l_end}"));
+                    self.statements(body);
+                    self.code.push(format!("    jmp {l_loop}"));
+                    self.code.push(format!("{l_end}:"));
+                }
+            }
+        }
+    }
+}
+
+fn collect_vars(stmts: &[Stmt], vars: &mut Vec<String>) {
+    fn expr_vars(e: &Expr, vars: &mut Vec<String>) {
+        match e {
+            Expr::Var(name) if !vars.contains(name) => vars.push(name.clone()),
+            Expr::Not(a) => expr_vars(a, vars),
+            Expr::And(a, b) | Expr::Or(a, b) | Expr::Xor(a, b) => {
+                expr_vars(a, vars);
+                expr_vars(b, vars);
+            }
+            _ => (),
+        }
+    }
+    for stmt in stmts {
+        match stmt {
+            Stmt::Assign(target, e) => {
+                expr_vars(e, vars);
+                if target != "stdout" && !vars.contains(target) {
+                    vars.push(target.clone());
+                }
+            }
+            Stmt::If(cond, then, otherwise) => {
+                expr_vars(cond, vars);
+                collect_vars(then, vars);
+                collect_vars(otherwise, vars);
+            }
+            Stmt::While(cond, body) => {
+                expr_vars(cond, vars);
+                collect_vars(body, vars);
+            }
+        }
+    }
+}
+
+pub fn compile(src: &str) -> String {
+    let cpu_type = src
+        .lines()
+        .next()
+        .unwrap_or("")
+        .split('#')
+        .next()
+        .unwrap()
+        .trim();
+    let nor = match cpu_type {
+        "NAND_CPU" => false,
+        "NOR_CPU" => true,
+        _ => {
+            eprintln!("First line: NAND_CPU or NOR_CPU");
+            std::process::exit(1);
+        }
+    };
+    let mut parser = Parser {
+        tokens: tokenizer(src),
+        pos: 0,
+    };
+    let stmts = parser.statements(false);
+
+    let mut varnames = vec![];
+    collect_vars(&stmts, &mut varnames);
+    if varnames.len() >= RAM_END as usize {
+        eprintln!("Out of RAM bits (variables)");
+        std::process::exit(1);
+    }
+    let mut cg = Codegen {
+        nor,
+        vars: HashMap::new(),
+        temp_base: varnames.len() as u8,
+        temp_next: 0,
+        temp_max: 0,
+        label_ct: 0,
+        code: vec![],
+    };
+    for (i, name) in varnames.iter().enumerate() {
+        cg.vars.insert(name.clone(), i as u8);
+    }
+    cg.statements(&stmts);
+
+    let mut asm = format!("{cpu_type}\n");
+    for (i, name) in varnames.iter().enumerate() {
+        asm.push_str(&format!("; 0x{i:02x}: {name}\n"));
+    }
+    if cg.temp_max > 0 {
+        let (first, last) = (cg.temp_base, cg.temp_base + cg.temp_max - 1);
+        asm.push_str(&format!("; 0x{first:02x}..0x{last:02x}: temporary\n"));
+    }
+    for line in cg.code {
+        asm.push_str(&line);
+        asm.push('\n');
+    }
+    asm
+}
@@ -0,0 +1,397 @@
+// Graphviz (DOT) export of a bit CPU program
+//
+// gates: one cluster for every straight-line region (basic block), the
+//        nodes are the nand/nor instructions, the edges are the RAM bits
+//        from the last writer to the reader (ellipse: value from outside
+//        of the block, box: value at the end of the block)
+// cfg:   basic blocks with jmp, call, skip and fallthrough edges, jmp [jl]
+//        and call [jl] lead to the "indirect" node (target of the latch)
+//
+// Addresses: 0xfc: call/ret in the call profile (header option "call"), else
+// RAM, 0xfd: stdin/stdout, 0xfe: low/skip, 0xff: high/jmp
+// Jump latch jl (jmp [jl], call [jl] of the source): a write of jl+1 jumps
+// to the latched target, a write of jl+2 calls it
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+const CALL: u8 = 0xfc;
+const IO: u8 = 0xfd;
+const SKIP: u8 = 0xfe;
+const JMP: u8 = 0xff;
+
+fn decode(code: u32) -> (u8, u8, u8) {
+    ((code >> 16) as u8, (code >> 8) as u8, code as u8)
+}
+
+fn target(src1: u8, src2: u8) -> usize {
+    (src1 as usize) << 8 | src2 as usize
+}
+
+// successor of jmp [jl], call [jl]: the latched target is dynamic
+const INDIRECT: usize = usize::MAX;
+
+// 0xfc writes of the program header options: call profile, ret encoding,
+// and the jump latches
+struct Profile<'a> {
+    call: bool,    // "call": 0xfc is call/ret
+    ret_msb: bool, // "ret=msb": ret with SRC1 MSB high, else 0xfc0000
+    latches: &'a BTreeSet<u8>,
+}
+
+impl<'a> Profile<'a> {
+    fn new(options: &[String], latches: &'a BTreeSet<u8>) -> Self {
+        Profile {
+            call: options.iter().any(|o| o == "call"),
+            ret_msb: options.iter().any(|o| o == "ret=msb"),
+            latches,
+        }
+    }
+
+    // jmp [jl], call [jl] (trigger << 16 | 0xffff): Some(call)
+    fn via_latch(&self, (dst, src1, src2): (u8, u8, u8)) -> Option<bool> {
+        if (src1, src2) != (JMP, JMP) || dst == CALL && self.call {
+            return None;
+        }
+        [false, true].into_iter().find(|&call| {
+            dst.checked_sub(1 + call as u8)
+                .is_some_and(|jl| self.latches.contains(&jl))
+        })
+    }
+
+    fn is_ret(&self, (dst, src1, src2): (u8, u8, u8)) -> bool {
+        self.call
+            && dst == CALL
+            && if self.ret_msb {
+                src1 & 0x80 != 0
+            } else {
+                src1 == 0 && src2 == 0
+            }
+    }
+
+    // next addresses of the instruction, ret: none (return address is dynamic)
+    fn successors(&self, pc: usize, code: u32) -> Vec<(usize, &'static str)> {
+        match decode(code) {
+            (JMP, s1, s2) => vec![(target(s1, s2), "jmp")],
+            instr if self.is_ret(instr) => vec![],
+            instr if self.via_latch(instr) == Some(false) => vec![(INDIRECT, "jmp")],
+            instr if self.via_latch(instr) == Some(true) => {
+                vec![(INDIRECT, "call"), (pc + 1, "return")]
+            }
+            (CALL, s1, s2) if self.call => vec![(target(s1, s2), "call"), (pc + 1, "return")],
+            (SKIP, ..) => vec![(pc + 1, ""), (pc + 2, "skip")],
+            _ => vec![(pc + 1, "")],
+        }
+    }
+}
+
+// start address --> end address (exclusive)
+fn basic_blocks(machine_code: &[u32], profile: &Profile) -> BTreeMap<usize, usize> {
+    let len = machine_code.len();
+    let mut leaders = BTreeSet::from([0]);
+    for (pc, &code) in machine_code.iter().enumerate() {
+        let succ = profile.successors(pc, code);
+        if succ.len() != 1 || succ[0].0 != pc + 1 {
+            leaders.extend(succ.iter().map(|&(next, _)| next));
+            leaders.insert(pc + 1);
+        }
+    }
+    let starts: Vec<usize> = leaders.into_iter().filter(|&l| l < len).collect();
+    starts
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| (start, *starts.get(i + 1).unwrap_or(&len)))
+        .collect()
+}
+
+struct Names {
+    bits: HashMap<u8, String>,
+    labels: HashMap<usize, String>,
+}
+
+impl Names {
+    fn new(addr_labels: &HashMap<String, u32>, equ_labels: &HashMap<String, u32>) -> Self {
+        let mut bits: BTreeMap<u8, Vec<&str>> = BTreeMap::new();
+        for (name, &addr) in equ_labels {
+            bits.entry(addr as u8).or_default().push(name);
+        }
+        let bits = bits
+            .into_iter()
+            .map(|(addr, mut names)| {
+                names.sort_unstable();
+                (addr, names.join("/"))
+            })
+            .collect();
+        let labels = addr_labels
+            .iter()
+            .map(|(name, &addr)| (addr as usize, name.clone()))
+            .collect();
+        Names { bits, labels }
+    }
+
+    fn bit(&self, addr: u8) -> String {
+        match self.bits.get(&addr) {
+            Some(name) => name.clone(),
+            None => format!("0x{addr:02x}"),
+        }
+    }
+
+    fn block(&self, start: usize, end: usize) -> String {
+        let range = format!("{start:04x}..{:04x}", end - 1);
+        match self.labels.get(&start) {
+            Some(label) => format!("{label}\\n{range}"),
+            None => range,
+        }
+    }
+}
+
+fn gate_graph(
+    machine_code: &[u32],
+    blocks: &BTreeMap<usize, usize>,
+    gate: &str,
+    names: &Names,
+    profile: &Profile,
+) -> String {
+    let mut dot =
+        String::from("digraph gates {\n    rankdir=LR;\n    node [fontname=monospace];\n");
+    for (&start, &end) in blocks {
+        dot.push_str(&format!("    subgraph cluster_{start} {{\n"));
+        dot.push_str(&format!("        label=\"{}\";\n", names.block(start, end)));
+        let mut writer: HashMap<u8, String> = HashMap::new();
+        let mut live_in: BTreeSet<u8> = BTreeSet::new();
+        let mut edges = vec![];
+        for (pc, &code) in machine_code.iter().enumerate().take(end).skip(start) {
+            let (dst, src1, src2) = decode(code);
+            let via = profile.via_latch((dst, src1, src2));
+            if dst == JMP || dst == CALL && profile.call || via.is_some() {
+                continue;
+            }
+            let node = format!("g{pc}");
+            let label = match dst {
+                SKIP => format!("{pc:04x}\\nskip_{gate}"),
+                IO => format!("{pc:04x}\\n{gate}\\n--> stdout"),
+                _ => format!("{pc:04x}\\n{gate}"),
+            };
+            dot.push_str(&format!(
+                "        {node} [shape=invhouse, label=\"{label}\"];\n"
+            ));
+            for src in [src1, src2] {
+                let from = match src {
+                    IO => {
+                        let stdin = format!("in{pc}_{}", edges.len());
+                        dot.push_str(&format!(
+                            "        {stdin} [shape=plaintext, label=\"stdin\"];\n"
+                        ));
+                        stdin
+                    }
+                    SKIP | JMP => {
+                        let value = format!("c{pc}_{}", edges.len());
+                        let label = if src == JMP { "1" } else { "0" };
+                        dot.push_str(&format!(
+                            "        {value} [shape=plaintext, label=\"{label}\"];\n"
+                        ));
+                        value
+                    }
+                    _ => match writer.get(&src) {
+                        Some(w) => w.clone(),
+                        None => {
+                            live_in.insert(src);
+                            format!("in{start}_{src}")
+                        }
+                    },
+                };
+                edges.push(format!(
+                    "        {from} -> {node} [label=\"{}\"];\n",
+                    names.bit(src)
+                ));
+            }
+            if dst <= CALL {
+                writer.insert(dst, node);
+            }
+        }
+        for bit in &live_in {
+            dot.push_str(&format!(
+                "        in{start}_{bit} [shape=ellipse, label=\"{}\"];\n",
+                names.bit(*bit)
+            ));
+        }
+        let mut live_out: Vec<(&u8, &String)> = writer.iter().collect();
+        live_out.sort_unstable();
+        for (bit, node) in live_out {
+            dot.push_str(&format!(
+                "        out{start}_{bit} [shape=box, label=\"{}\"];\n",
+                names.bit(*bit)
+            ));
+            edges.push(format!("        {node} -> out{start}_{bit};\n"));
+        }
+        for edge in edges {
+            dot.push_str(&edge);
+        }
+        dot.push_str("    }\n");
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+fn cfg_graph(
+    machine_code: &[u32],
+    blocks: &BTreeMap<usize, usize>,
+    names: &Names,
+    profile: &Profile,
+) -> String {
+    let len = machine_code.len();
+    let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+    dot.push_str("    end [shape=doublecircle, label=\"end\"];\n");
+    if machine_code
+        .iter()
+        .any(|&code| profile.via_latch(decode(code)).is_some())
+    {
+        dot.push_str("    indirect [shape=diamond, label=\"[latch]\"];\n");
+    }
+    let block_of = |pc: usize| match blocks.range(..=pc).next_back() {
+        _ if pc == INDIRECT => "indirect".to_string(),
+        Some((&start, _)) if pc < len => format!("b{start}"),
+        _ => "end".to_string(),
+    };
+    for (&start, &end) in blocks {
+        let last = end - 1;
+        let ret = profile.is_ret(decode(machine_code[last]));
+        let label = names.block(start, end) + if ret { "\\nret" } else { "" };
+        dot.push_str(&format!("    b{start} [label=\"{label}\"];\n"));
+        for (next, kind) in profile.successors(last, machine_code[last]) {
+            let style = match kind {
+                "call" => ", style=bold",
+                "return" => ", style=dashed",
+                _ => "",
+            };
+            dot.push_str(&format!(
+                "    b{start} -> {} [label=\"{kind}\"{style}];\n",
+                block_of(next)
+            ));
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+// (gate DAG, control-flow graph)
+pub fn export(
+    machine_code: &[u32],
+    cpu_type: &str,
+    options: &[String],
+    latches: &BTreeSet<u8>,
+    addr_labels: &HashMap<String, u32>,
+    equ_labels: &HashMap<String, u32>,
+) -> (String, String) {
+    let gate = cpu_type.to_lowercase().replace("_cpu", "");
+    let names = Names::new(addr_labels, equ_labels);
+    let profile = Profile::new(options, latches);
+    let blocks = basic_blocks(machine_code, &profile);
+    (
+        gate_graph(machine_code, &blocks, &gate, &names, &profile),
+        cfg_graph(machine_code, &blocks, &names, &profile),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::export;
+    use std::collections::{BTreeSet, HashMap};
+
+    // call 0x0003, RAM 0x00 from 0xfc, ret, ret=msb
+    const PROG: [u32; 4] = [0xfc0003, 0x00fcfc, 0xfc0000, 0xfc8000];
+
+    fn cfg(options: &[&str]) -> String {
+        let options: Vec<String> = options.iter().map(|o| o.to_string()).collect();
+        let (_, cfg) = export(
+            &PROG,
+            "NAND_CPU",
+            &options,
+            &BTreeSet::new(),
+            &HashMap::new(),
+            &HashMap::new(),
+        );
+        cfg
+    }
+
+    // call 0x0003, skip, jmp 0x0000, ret
+    const LOOP: [u32; 4] = [0xfc0003, 0xfe0001, 0xff0000, 0xfc0000];
+
+    fn graphs() -> (String, String) {
+        let labels = HashMap::from([("sub".to_string(), 3)]);
+        let equs = HashMap::from([("a".to_string(), 0), ("b".to_string(), 1)]);
+        let call = ["call".to_string()];
+        export(&LOOP, "NAND_CPU", &call, &BTreeSet::new(), &labels, &equs)
+    }
+
+    // jump latch 0x10: latch bit 1, call [0x10], jmp [0x10], RAM 0x00 = 0
+    const LATCH: [u32; 4] = [0x10fefe, 0x12ffff, 0x11ffff, 0x00ffff];
+
+    #[test]
+    fn latch() {
+        let call = ["call".to_string()];
+        let graphs = |latches: &[u8]| {
+            let latches = BTreeSet::from_iter(latches.iter().copied());
+            export(
+                &LATCH,
+                "NAND_CPU",
+                &call,
+                &latches,
+                &HashMap::new(),
+                &HashMap::new(),
+            )
+        };
+        let (gates, cfg) = graphs(&[0x10]);
+        assert!(cfg.contains("indirect [shape=diamond"));
+        assert!(cfg.contains("b0 -> indirect [label=\"call\", style=bold]"));
+        assert!(cfg.contains("b0 -> b2 [label=\"return\", style=dashed]"));
+        assert!(cfg.contains("b2 -> indirect [label=\"jmp\"]"));
+        assert!(!cfg.contains("b2 -> b3"));
+        assert!(cfg.contains("b3 -> end"));
+        assert!(gates.contains("g0 ") && gates.contains("g3 "));
+        assert!(!gates.contains("g1 ") && !gates.contains("g2 "));
+        let (_, cfg) = graphs(&[]); // plain RAM writes of nand(0xff, 0xff)
+        assert!(!cfg.contains("indirect") && cfg.contains("b0 -> end"));
+    }
+
+    #[test]
+    fn cfg_edges() {
+        let (_, cfg) = graphs();
+        assert!(cfg.contains("b0 -> b3 [label=\"call\", style=bold]"));
+        assert!(cfg.contains("b0 -> b1 [label=\"return\", style=dashed]"));
+        assert!(cfg.contains("b1 -> b2 [label=\"\"]"));
+        assert!(cfg.contains("b1 -> b3 [label=\"skip\"]"));
+        assert!(cfg.contains("b2 -> b0 [label=\"jmp\"]"));
+        assert!(cfg.contains("\\nret\"]"));
+        assert!(!cfg.contains("b3 ->"));
+    }
+
+    #[test]
+    fn gate_nodes() {
+        let (gates, _) = graphs();
+        assert!(gates.starts_with("digraph gates {"));
+        assert!(gates.contains("g1 "));
+        assert!(!gates.contains("g0 ") && !gates.contains("g2 ") && !gates.contains("g3 "));
+    }
+
+    #[test]
+    fn base_profile() {
+        let cfg = cfg(&[]);
+        assert!(!cfg.contains("call") && !cfg.contains("ret"));
+        assert!(cfg.contains("b0 -> end"));
+    }
+
+    #[test]
+    fn call_profile() {
+        let cfg = cfg(&["call"]);
+        assert!(cfg.contains("b0 -> b3 [label=\"call\", style=bold]"));
+        assert!(cfg.contains("0001..0002\\nret"));
+        assert!(cfg.contains("b3 -> end")); // ret=msb encoding: call 0x8000
+    }
+
+    #[test]
+    fn ret_msb() {
+        let cfg = cfg(&["call", "ret=msb"]);
+        assert!(cfg.contains("b0 -> b3 [label=\"call\", style=bold]"));
+        assert!(cfg.contains("0003..0003\\nret"));
+    }
+}
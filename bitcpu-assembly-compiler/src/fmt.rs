@@ -0,0 +1,176 @@
+// Source formatter: canonical layout of the assembly files
+//
+// label:                     ; column 0
+//     dst = nand(a, b)       ; 4 space indent, "a, b" comma spacing
+// name   equ 0x03            ; equ names aligned in a block
+//
+// Trailing comments are aligned inside a block (lines between two blank
+// lines, labels or comment-only lines). The comment text is kept as is.
+
+const INDENT: &str = "    ";
+
+enum Line {
+    Empty,
+    Comment(bool, String),       // indented, comment text
+    Label(String),               // label name with ':'
+    Directive(String, String),   // %include, %macro ... , comment
+    Equ(String, String, String), // name, value, comment
+    Code(String, String),        // code, comment
+}
+
+// split the line at the first comment char: (code, comment)
+fn split_comment(line: &str) -> (&str, &str) {
+    match line.find([';', '#']) {
+        Some(pos) => (line[..pos].trim(), line[pos..].trim_end()),
+        None => (line.trim(), ""),
+    }
+}
+
+// like splitter(), but keep the case of the words
+fn words(code: &str) -> Vec<&str> {
+    code.split([' ', '\t', '(', ',', ')'])
+        .filter(|w| !w.is_empty())
+        .collect()
+}
+
+fn format_code(code: &str) -> String {
+    let w = words(code);
+    let lower: Vec<String> = w.iter().map(|s| s.to_lowercase()).collect();
+    match lower.iter().map(|s| s.as_str()).collect::<Vec<_>>()[..] {
+        [_, "=", op @ ("nand" | "nor"), _, _] => format!("{} = {op}({}, {})", w[0], w[3], w[4]),
+        [op @ ("skip_nand" | "skip_nor"), _, _] => format!("{op}({}, {})", w[1], w[2]),
+        [op @ ("jmp" | "call"), _] => format!("{op} {}", w[1]),
+        ["ret"] => "ret".to_string(),
+        [op @ ("point" | "target"), _, _] => format!("{op} {}, {}", w[1], w[2]),
+        _ => code.to_string(),
+    }
+}
+
+fn classify(linenum: usize, line: &str) -> Line {
+    let (code, comment) = split_comment(line);
+    let indented = line.starts_with([' ', '\t']);
+    if code.is_empty() {
+        if comment.is_empty() {
+            Line::Empty
+        } else {
+            Line::Comment(indented, comment.to_string())
+        }
+    } else if linenum == 0 {
+        Line::Directive(code.to_string(), comment.to_string()) // CPU type
+    } else if comment.is_empty() && code.ends_with(':') && words(code).len() == 1 {
+        Line::Label(code.to_string())
+    } else if code.starts_with('%') {
+        Line::Directive(code.to_string(), comment.to_string())
+    } else {
+        let w = words(code);
+        if w.len() == 3 && w[1].eq_ignore_ascii_case("equ") {
+            Line::Equ(w[0].to_string(), w[2].to_string(), comment.to_string())
+        } else {
+            Line::Code(format_code(code), comment.to_string())
+        }
+    }
+}
+
+// aligned block: (code, comment) pairs
+fn flush_block(block: &mut Vec<(String, String)>, out: &mut Vec<String>) {
+    let column = block
+        .iter()
+        .filter(|(_, comment)| !comment.is_empty())
+        .map(|(code, _)| code.len())
+        .max()
+        .unwrap_or(0);
+    for (code, comment) in block.drain(..) {
+        if comment.is_empty() {
+            out.push(code);
+        } else {
+            out.push(format!("{code:column$} {comment}"));
+        }
+    }
+}
+
+pub fn format_source(src: &str) -> String {
+    let lines: Vec<Line> = src
+        .lines()
+        .enumerate()
+        .map(|(i, l)| classify(i, l))
+        .collect();
+    let mut out = vec![];
+    let mut block = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        match &lines[i] {
+            Line::Empty => {
+                flush_block(&mut block, &mut out);
+                out.push(String::new());
+            }
+            Line::Comment(indented, comment) => {
+                flush_block(&mut block, &mut out);
+                let indent = if *indented { INDENT } else { "" };
+                out.push(format!("{indent}{comment}"));
+            }
+            Line::Label(label) => {
+                flush_block(&mut block, &mut out);
+                out.push(label.clone());
+            }
+            Line::Directive(code, comment) => block.push((code.clone(), comment.clone())),
+            Line::Code(code, comment) => block.push((format!("{INDENT}{code}"), comment.clone())),
+            Line::Equ(..) => {
+                // equ block: align the names and the values
+                let start = i;
+                while i + 1 < lines.len() && matches!(lines[i + 1], Line::Equ(..)) {
+                    i += 1;
+                }
+                let equs = &lines[start..=i];
+                let width = equs
+                    .iter()
+                    .map(|l| match l {
+                        Line::Equ(name, ..) => name.len(),
+                        _ => 0,
+                    })
+                    .max()
+                    .unwrap_or(0);
+                for l in equs {
+                    if let Line::Equ(name, value, comment) = l {
+                        block.push((format!("{name:width$} equ {value}"), comment.clone()));
+                    }
+                }
+            }
+        }
+        i += 1;
+    }
+    flush_block(&mut block, &mut out);
+    let mut formatted = out.join("\n");
+    formatted.push('\n');
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_source;
+    use crate::{assemble, listing};
+
+    const EXAMPLE: &str = include_str!("../sample/example-01.asm");
+
+    #[test]
+    fn same_listing() {
+        let formatted = format_source(EXAMPLE);
+        assert_ne!(formatted, EXAMPLE);
+        let before = listing(&assemble(EXAMPLE, "example-01.asm", false));
+        let after = listing(&assemble(&formatted, "example-01.asm", false));
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn idempotent() {
+        let formatted = format_source(EXAMPLE);
+        assert_eq!(format_source(&formatted), formatted);
+    }
+
+    #[test]
+    fn canonical_layout() {
+        let src =
+            "NAND_CPU\na equ 1\ntmp equ 3 ; temp\nstart:\n  tmp = NAND(a   a) ; x\n JMP start\n";
+        let expected = "NAND_CPU\na   equ 1\ntmp equ 3 ; temp\nstart:\n    tmp = nand(a, a) ; x\n    jmp start\n";
+        assert_eq!(format_source(src), expected);
+    }
+}
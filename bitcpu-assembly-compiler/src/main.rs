@@ -1,11 +1,15 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::env;
 use std::fs;
-use std::io::BufWriter;
-use std::io::Write;
 use std::iter::FromIterator;
 use std::path::Path;
 
+mod bexp;
+mod dot;
+mod fmt;
+mod netlist;
+mod synth;
+
 const DEBUG: bool = true;
 
 // comment: # and ;
@@ -16,6 +20,10 @@ const DEBUG: bool = true;
 // a = nand(a, b)
 // jmp label
 // jnz_nand(a, b) label
+// point ix, table     ; index register pointer (equ or number): 8 writes of
+//                     ; ix, MSB first
+// target jl, label    ; jump latch target (label or number): 16 writes of jl
+// jmp [jl], call [jl] ; jmp, call of the latched target (--device jump@jl)
 
 fn help() {
     println!("Valid instructions:");
@@ -25,7 +33,20 @@ fn help() {
     println!("   dest = nand(a, b)      ; nand with labels");
     println!("   0x0c = nand(0xff, 12)  ; nand with address");
     println!("   skip_nand(a, b)        ; skip next instruction");
-    println!("   jmp addr, call addr, ret");
+    println!("   nor(a, b), skip_nor(a, b) in NOR_CPU");
+    println!("   jmp addr, call addr, ret   ; ret: 0xfc8000 with header ret=msb");
+    println!("   point ix, value        ; set the index register (--device index@ix)");
+    println!("   target jl, addr        ; set the jump latch (--device jump@jl)");
+    println!("   jmp [jl], call [jl]    ; jmp, call of the latched address");
+}
+
+fn usage() {
+    eprintln!("usage: bitcpu-assembly-compiler <file.asm> [fmt|dot]");
+    eprintln!("       bitcpu-assembly-compiler <file.bexp>  ; boolean expressions");
+    eprintln!("       bitcpu-assembly-compiler <file.aag|file.blif> [nor]  ; gate netlists");
+    eprintln!("       bitcpu-assembly-compiler <file.tt> [nor]  ; truth table --> macro (.inc)");
+    eprintln!("   fmt: rewrite the file in the canonical source layout");
+    eprintln!("   dot: gate and control-flow graphs (also from .lst, .nand)");
 }
 
 fn splitter(s_in: &str) -> Vec<String> {
@@ -53,6 +74,10 @@ fn splitter(s_in: &str) -> Vec<String> {
     words
 }
 
+fn is_gate(word: &str) -> bool {
+    word == "nand" || word == "nor"
+}
+
 fn parsenum(s: &str, linenum: usize) -> u32 {
     if s.starts_with("0x") {
         if let Ok(num) = u32::from_str_radix(s.strip_prefix("0x").unwrap(), 16) {
@@ -77,11 +102,15 @@ fn equ_get(equ_hmap: &HashMap<String, u32>, keyword: &str, linenum: usize) -> u3
     }
 }
 
+// Source file and line (from 1) of a preprocessed line
+type Origin = (String, usize);
+
 // Preprocessing: macro and included file
 fn preprocessor_include(
     assembly_code: &str,
     filename: &str,
     fnamelist: &mut Vec<String>,
+    origins: &mut Vec<Origin>,
 ) -> String {
     let parentdir = Path::new(filename)
         .parent()
@@ -90,7 +119,7 @@ fn preprocessor_include(
         .unwrap()
         .to_owned();
     let mut linearized = String::new();
-    for s in assembly_code.lines() {
+    for (linenum, s) in assembly_code.lines().enumerate() {
         let words = splitter(s);
         if !words.is_empty() && words[0] == "%include" {
             let fname = parentdir.clone() + "/" + &words[1].replace('"', "");
@@ -103,21 +132,26 @@ fn preprocessor_include(
                 &fs::read_to_string(&fname).expect("File not found."),
                 &fname,
                 fnamelist,
+                origins,
             );
             linearized.push_str(&inner);
         } else {
             linearized.push_str(s);
             linearized.push('\n');
+            origins.push((filename.to_string(), linenum + 1));
         }
     }
     linearized
 }
 
-fn preprocessor_macro(assembly_code: &str) -> String {
+fn preprocessor_macro(assembly_code: &str, origins: &mut Vec<Origin>) -> String {
+    let mut origins_in = std::mem::take(origins).into_iter();
     let mut macro_hash = HashMap::new();
     let mut linearized = String::new();
+    #[allow(dead_code)] // argnum and reference_num: not yet used
     struct MacroStruct {
         macro_codes: String,
+        macro_origins: Vec<Origin>,
         macro_argnum: u8,
         reference_num: u32,
     }
@@ -125,7 +159,9 @@ fn preprocessor_macro(assembly_code: &str) -> String {
     let mut macro_name = String::new();
     let mut macro_argnum = 0;
     let mut macro_codes = String::new();
+    let mut macro_origins = vec![];
     for s in assembly_code.lines() {
+        let origin = origins_in.next().unwrap_or_default();
         let words = splitter(s);
         if !words.is_empty() {
             match words[0].as_str() {
@@ -138,6 +174,7 @@ fn preprocessor_macro(assembly_code: &str) -> String {
                     let mstr = MacroStruct {
                         macro_argnum,
                         macro_codes: macro_codes.clone(),
+                        macro_origins: std::mem::take(&mut macro_origins),
                         reference_num: 0,
                     };
                     macro_hash.insert(macro_name.clone(), mstr);
@@ -150,12 +187,17 @@ fn preprocessor_macro(assembly_code: &str) -> String {
                         linearized.push_str(&("; macro ".to_owned() + &words[0] + "\n"));
                         linearized.push_str(&macro_data.macro_codes);
                         linearized.push_str(&("; endmacro".to_owned() + &words[0] + "\n"));
+                        origins.push(origin.clone());
+                        origins.extend(macro_data.macro_origins.iter().cloned());
+                        origins.push(origin);
                     } else if macro_mode {
                         macro_codes.push_str(s);
                         macro_codes.push('\n');
+                        macro_origins.push(origin);
                     } else {
                         linearized.push_str(s);
                         linearized.push('\n');
+                        origins.push(origin);
                     }
                 }
             }
@@ -164,21 +206,40 @@ fn preprocessor_macro(assembly_code: &str) -> String {
     linearized
 }
 
+// Result of the assembler, labels for the exporters
+struct Assembled {
+    cpu_type: String,
+    options: Vec<String>, // header options for the emulator, e.g. "call"
+    machine_code: Vec<u32>,
+    addr_labels: HashMap<String, u32>,
+    equ_labels: HashMap<String, u32>,
+    latches: BTreeSet<u8>, // jump latches of jmp [jl], call [jl]
+    source: Vec<Origin>,   // source line of every instruction
+}
+
 // Ccompile "linearized" file (here is not include and macro)
-fn assembler(assembly_code: &str) -> (String, Vec<u32>) {
+fn assembler(assembly_code: &str, debug: bool) -> Assembled {
     let mut cpu_type = String::new();
+    let mut options = vec![];
+    let mut ret_msb = false; // ret encoding of the header option ret=msb
+    let mut gate = String::new(); // nand or nor
     let mut machine_code = vec![];
     let mut addr_labels = HashMap::new();
     let mut equ_labels = HashMap::new();
+    let mut latches = BTreeSet::new();
+    let mut lines = vec![]; // line of every instruction
     let mut address = 0;
 
     // Stage-1: Process address labels (for forward jmp)
     for (linenum, line) in assembly_code.lines().enumerate() {
         if linenum == 0 {
-            let cpu_types = ["nand_cpu"];
+            let cpu_types = ["nand_cpu", "nor_cpu"];
             let words: Vec<_> = splitter(line);
             if cpu_types.iter().any(|e| words[0].contains(e)) {
                 cpu_type = words[0].to_uppercase();
+                gate = words[0].trim_end_matches("_cpu").to_string();
+                options = words[1..].to_vec();
+                ret_msb = options.iter().any(|o| o == "ret=msb");
             } else {
                 eprintln!("First line must be one of these: {:?}", cpu_types);
                 help();
@@ -191,18 +252,22 @@ fn assembler(assembly_code: &str) -> (String, Vec<u32>) {
             } else {
                 let words: Vec<_> = splitter(line);
                 if (!words.is_empty()
-                    && ["skip_nand", "jmp", "call", "ret"]
+                    && ["skip_nand", "skip_nor", "jmp", "call", "ret"]
                         .iter()
                         .any(|e| words[0].contains(e)))
-                    || (words.len() > 1 && (words[1] == "=" && words[2] == "nand"))
+                    || (words.len() > 1 && (words[1] == "=" && is_gate(&words[2])))
                 {
                     address += 1;
+                } else if words.len() == 3 && words[0] == "point" {
+                    address += 8;
+                } else if words.len() == 3 && words[0] == "target" {
+                    address += 16;
                 }
             }
         }
     }
 
-    if DEBUG {
+    if debug {
         println!("Debug addr_labels: {addr_labels:?}");
     }
 
@@ -213,21 +278,39 @@ fn assembler(assembly_code: &str) -> (String, Vec<u32>) {
             if words.is_empty() {
                 continue;
             }
-            if (["skip_nand", "jmp", "call", "ret"]
+            if (["skip_nand", "skip_nor", "jmp", "call", "ret"]
                 .iter()
                 .any(|e| words[0].contains(e)))
                 || (words.len() >= 2
-                    && ((words[1] == "=" && words[2] == "nand") || words[1] == "equ"))
+                    && ((words[1] == "=" && is_gate(&words[2])) || words[1] == "equ"))
+                || (words.len() == 3 && (words[0] == "point" || words[0] == "target"))
             {
-                if DEBUG {
+                if debug {
                     println!("Debug: {:?} --> {:?}", line, words);
                 }
                 if words.len() == 3 && words[1] == "equ" {
                     equ_labels.insert(words[0].clone(), parsenum(&words[2], linenum));
-                } else if words[0] == "skip_nand" {
+                } else if (words[0] == "skip_nand" || words[0] == "skip_nor")
+                    && words[0] != "skip_".to_owned() + &gate
+                    || (words.len() > 2 && words[1] == "=" && is_gate(&words[2]))
+                        && words[2] != gate
+                {
+                    eprintln!("Syntax error in line {linenum} ({cpu_type}: only {gate})");
+                    std::process::exit(1);
+                } else if words[0] == "skip_nand" || words[0] == "skip_nor" {
                     let a = equ_get(&equ_labels, &words[1], linenum);
                     let b = equ_get(&equ_labels, &words[2], linenum);
                     machine_code.push(0xfe << 16 | a << 8 | b);
+                } else if let Some(latch) = words
+                    .get(1)
+                    .and_then(|w| w.strip_prefix('[')?.strip_suffix(']'))
+                    .filter(|_| words[0] == "jmp" || words[0] == "call")
+                {
+                    // write of latch+1: jmp, latch+2: call
+                    let latch = equ_get(&equ_labels, latch, linenum);
+                    latches.insert(latch as u8);
+                    let trigger = latch + 1 + (words[0] == "call") as u32;
+                    machine_code.push(trigger << 16 | 0xffff);
                 } else if words[0] == "jmp" {
                     let address = if let Some(&addr) = addr_labels.get(&words[1]) {
                         addr
@@ -241,10 +324,47 @@ fn assembler(assembly_code: &str) -> (String, Vec<u32>) {
                     } else {
                         parsenum(&words[1], linenum)
                     };
+                    if ret_msb && address > 0x7fff {
+                        eprintln!("Syntax error in line {linenum} (ret=msb: call 0x0000..0x7fff)");
+                        std::process::exit(1);
+                    }
                     machine_code.push(0xfc0000 | address);
+                } else if words[0] == "ret" && ret_msb {
+                    machine_code.push(0xfc8000); // src1 MSB high
                 } else if words[0] == "ret" {
                     machine_code.push(0xfc0000); // address 0x0000 start, not callable
-                } else if words[1] == "=" && words[2] == "nand" {
+                } else if words[0] == "point" {
+                    // shift the pointer bits MSB first: nand/nor(0xfe, 0xfe) is 1,
+                    // nand/nor(0xff, 0xff) is 0
+                    let ix = equ_get(&equ_labels, &words[1], linenum);
+                    let value = match equ_labels.get(&words[2]) {
+                        Some(&bit) => bit,
+                        None => parsenum(&words[2], linenum),
+                    };
+                    if value > 0xff {
+                        eprintln!("Syntax error in line {linenum} (pointer out of 0x00..0xff)");
+                        std::process::exit(1);
+                    }
+                    for n in (0..8).rev() {
+                        let src = if value >> n & 1 != 0 { 0xfe } else { 0xff };
+                        machine_code.push(ix << 16 | src << 8 | src);
+                    }
+                } else if words[0] == "target" {
+                    let jl = equ_get(&equ_labels, &words[1], linenum);
+                    let address = if let Some(&addr) = addr_labels.get(&words[2]) {
+                        addr
+                    } else {
+                        parsenum(&words[2], linenum)
+                    };
+                    if address > 0xffff {
+                        eprintln!("Syntax error in line {linenum} (target out of 0x0000..0xffff)");
+                        std::process::exit(1);
+                    }
+                    for n in (0..16).rev() {
+                        let src = if address >> n & 1 != 0 { 0xfe } else { 0xff };
+                        machine_code.push(jl << 16 | src << 8 | src);
+                    }
+                } else if words[1] == "=" && is_gate(&words[2]) {
                     let d = equ_get(&equ_labels, &words[0], linenum);
                     let a = equ_get(&equ_labels, &words[3], linenum);
                     let b = equ_get(&equ_labels, &words[4], linenum);
@@ -253,6 +373,7 @@ fn assembler(assembly_code: &str) -> (String, Vec<u32>) {
                     eprintln!("Syntax error in line {linenum} (not a valid token syntax)");
                     std::process::exit(1);
                 }
+                lines.resize(machine_code.len(), linenum);
             } else {
                 eprintln!("Syntax error in line {linenum} (unknown token)");
                 help();
@@ -260,40 +381,211 @@ fn assembler(assembly_code: &str) -> (String, Vec<u32>) {
             }
         }
     }
-    (cpu_type, machine_code)
+    Assembled {
+        cpu_type,
+        options,
+        machine_code,
+        addr_labels,
+        equ_labels,
+        latches,
+        source: lines.into_iter().map(|l| (String::new(), l)).collect(),
+    }
+}
+
+// Preprocess and compile the source of filename (path of the includes)
+fn assemble(assembly_code: &str, filename: &str, debug: bool) -> Assembled {
+    let mut filenamevec = vec![];
+    let mut origins = vec![];
+    let assembly_code =
+        preprocessor_include(assembly_code, filename, &mut filenamevec, &mut origins);
+    let assembly_code = preprocessor_macro(&assembly_code, &mut origins);
+    if debug {
+        for s in assembly_code.lines() {
+            println!("{s}");
+        }
+    }
+    let mut assembled = assembler(&assembly_code, debug);
+    for source in assembled.source.iter_mut() {
+        *source = origins[source.1].clone();
+    }
+    assembled
+}
+
+// Rewrite the file in canonical layout, if the meaning is not changed
+fn format_file(filename: &str) {
+    let assembly_code = fs::read_to_string(filename).expect("File not found.");
+    let formatted = fmt::format_source(&assembly_code);
+    for (linenum, (orig, new)) in assembly_code.lines().zip(formatted.lines()).enumerate() {
+        if splitter(orig) != splitter(new) {
+            eprintln!(
+                "fmt: tokens changed in line {}, file not written",
+                linenum + 1
+            );
+            std::process::exit(1);
+        }
+    }
+    // include files have no CPU type line, these are checked only by tokens
+    let output = |code: &str| listing(&assemble(code, filename, false));
+    let first_line = splitter(assembly_code.lines().next().unwrap_or(""));
+    if first_line
+        .first()
+        .is_some_and(|w| w.contains("nand_cpu") || w.contains("nor_cpu"))
+        && output(&assembly_code) != output(&formatted)
+    {
+        eprintln!("fmt: assembled output changed, file not written");
+        std::process::exit(1);
+    }
+    if formatted != assembly_code {
+        fs::write(filename, formatted).expect("Failed to write file.");
+    }
+}
+
+// Emulator text format of the assembled program: header and instructions
+fn listing(assembled: &Assembled) -> String {
+    let mut header = vec![assembled.cpu_type.as_str()];
+    header.extend(assembled.options.iter().map(String::as_str));
+    let mut lst = header.join(" ") + "\n";
+    for code in &assembled.machine_code {
+        lst.push_str(&format!("0x{code:06x}\n"));
+    }
+    lst
+}
+
+// Emulator text format: header (CPU type, options) and the instructions
+// (hex or dec)
+fn parse_listing(src: &str) -> (String, Vec<String>, Vec<u32>) {
+    let mut lines = src
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.split('#').next().unwrap().trim()))
+        .filter(|(_, line)| !line.is_empty());
+    let mut header = lines.next().map_or("", |(_, line)| line).split_whitespace();
+    let cpu_type = header.next().unwrap_or("").to_string();
+    let options = header.map(str::to_lowercase).collect();
+    let machine_code = lines.map(|(i, line)| parsenum(line, i)).collect();
+    (cpu_type, options, machine_code)
+}
+
+// Write the gate DAG and the control-flow graph
+fn export_dot(assembled: &Assembled, basename: &str) {
+    let (gates, cfg) = dot::export(
+        &assembled.machine_code,
+        &assembled.cpu_type,
+        &assembled.options,
+        &assembled.latches,
+        &assembled.addr_labels,
+        &assembled.equ_labels,
+    );
+    fs::write(basename.to_owned() + ".gates.dot", gates).expect("Failed to write file.");
+    fs::write(basename.to_owned() + ".cfg.dot", cfg).expect("Failed to write file.");
+}
+
+// Symbol file for the emulators (debugger, traces), one symbol per line:
+// "label <name> <pc>", "equ <name> <RAM bit>" or "line <pc> <line> <file>"
+fn symbol_file(assembled: &Assembled, filename: &str) -> String {
+    let mut labels: Vec<(&u32, &String)> = assembled
+        .addr_labels
+        .iter()
+        .map(|(name, addr)| (addr, name))
+        .collect();
+    labels.sort_unstable();
+    let mut equs: Vec<(&u32, &String)> = assembled
+        .equ_labels
+        .iter()
+        .map(|(name, addr)| (addr, name))
+        .collect();
+    equs.sort_unstable();
+    let mut sym = format!("# symbols of {filename}\n");
+    for (addr, name) in labels {
+        sym.push_str(&format!("label {name} 0x{addr:04x}\n"));
+    }
+    for (addr, name) in equs {
+        sym.push_str(&format!("equ {name} 0x{addr:02x}\n"));
+    }
+    for (pc, (file, line)) in assembled.source.iter().enumerate() {
+        sym.push_str(&format!("line 0x{pc:04x} {line} {file}\n"));
+    }
+    sym
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        usage();
+        std::process::exit(1);
+    }
     let filename = &args[1];
+    if args.len() > 2 && args[2] == "fmt" {
+        format_file(filename);
+        return;
+    }
     let basename = Path::new(filename)
         .file_stem()
         .unwrap()
         .to_str()
         .unwrap()
         .to_owned();
-    let assembly_code = fs::read_to_string(filename).expect("File not found.");
-    let mut filenamevec = vec![];
-    let assembly_code = preprocessor_include(&assembly_code, filename, &mut filenamevec);
-    let assembly_code = preprocessor_macro(&assembly_code);
-    if DEBUG {
-        for s in assembly_code.lines() {
-            println!("{s}");
-        }
+    let mut assembly_code = fs::read_to_string(filename).expect("File not found.");
+    let dot_mode = args.len() > 2 && args[2] == "dot";
+    if dot_mode
+        && Path::new(filename)
+            .extension()
+            .is_some_and(|ext| ext == "lst" || ext == "nand" || ext == "nor")
+    {
+        let (cpu_type, options, machine_code) = parse_listing(&assembly_code);
+        let assembled = Assembled {
+            cpu_type,
+            options,
+            machine_code,
+            addr_labels: HashMap::new(),
+            equ_labels: HashMap::new(),
+            latches: BTreeSet::new(),
+            source: vec![],
+        };
+        export_dot(&assembled, &basename);
+        return;
     }
-    let (cpu_type, machine_code) = assembler(&assembly_code);
-
+    if Path::new(filename)
+        .extension()
+        .is_some_and(|ext| ext == "aag" || ext == "blif")
+    {
+        let nor = args.len() > 2 && args[2] == "nor";
+        let prog = netlist::convert(&assembly_code, filename, nor);
+        fs::write(basename + ".lst", prog).expect("Failed to write file.");
+        return;
+    }
+    if Path::new(filename)
+        .extension()
+        .is_some_and(|ext| ext == "tt")
+    {
+        let nor = args.len() > 2 && args[2] == "nor";
+        let inc = synth::synthesize(&assembly_code, filename, &basename, nor);
+        fs::write(basename + ".inc", inc).expect("Failed to write file.");
+        return;
+    }
+    if Path::new(filename)
+        .extension()
+        .is_some_and(|ext| ext == "bexp")
+    {
+        assembly_code = bexp::compile(&assembly_code);
+    }
+    let mut assembled = assemble(&assembly_code, filename, DEBUG && !dot_mode);
+    if Path::new(filename)
+        .extension()
+        .is_some_and(|ext| ext == "bexp")
+    {
+        assembled.source.clear(); // lines of the generated assembly
+    }
+    if dot_mode {
+        export_dot(&assembled, &basename);
+        return;
+    }
+    let symbols = symbol_file(&assembled, filename);
+    fs::write(basename.to_owned() + ".sym", symbols).expect("Failed to write file.");
     if DEBUG {
-        for (i, code) in machine_code.iter().enumerate() {
+        for (i, code) in assembled.machine_code.iter().enumerate() {
             println!("Debug code({i:4}): {:06x}", code);
         }
     }
-
-    let file = fs::File::create(basename + ".lst").unwrap();
-    let mut writer = BufWriter::new(file);
-    writeln!(&mut writer, "{cpu_type}").unwrap();
-    for code in machine_code {
-        writeln!(&mut writer, "0x{code:06x}").unwrap();
-    }
-    writer.flush().unwrap();
+    fs::write(basename + ".lst", listing(&assembled)).expect("Failed to write file.");
 }
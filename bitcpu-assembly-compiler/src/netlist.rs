@@ -0,0 +1,444 @@
+// Combinational gate netlists (AIGER .aag, BLIF) --> bit CPU program
+//
+// The netlist is converted to an and-inverter graph (AIG). The inputs are
+// read from stdin (0xfd) in the order of the netlist, the outputs are
+// written to stdout in the order of the netlist. The nodes get RAM bits,
+// which are reused after the last reader of the node.
+//
+// Output: emulator text format (first line: NAND_CPU or NOR_CPU)
+
+use std::collections::{BTreeSet, HashMap};
+
+const IO: u8 = 0xfd;
+const LOW: u8 = 0xfe;
+const HIGH: u8 = 0xff;
+const RAM_END: u8 = 0xfc; // 0xfc: call/ret in bitcpu-call
+
+// literal: node * 2 + negated, node 0: const false
+pub(crate) type Lit = u32;
+pub(crate) const FALSE: Lit = 0;
+pub(crate) const TRUE: Lit = 1;
+
+#[derive(Default)]
+pub(crate) struct Aig {
+    pub(crate) inputs: Vec<String>,
+    pub(crate) outputs: Vec<(String, Lit)>,
+    pub(crate) ands: Vec<(Lit, Lit)>, // node: 1 + inputs.len() + index
+    strash: HashMap<(Lit, Lit), Lit>,
+}
+
+impl Aig {
+    pub(crate) fn input(&mut self, name: &str) -> Lit {
+        self.inputs.push(name.to_string());
+        self.inputs.len() as Lit * 2
+    }
+
+    pub(crate) fn and(&mut self, a: Lit, b: Lit) -> Lit {
+        let (a, b) = if a < b { (a, b) } else { (b, a) };
+        if a == FALSE || a == b ^ 1 {
+            return FALSE;
+        }
+        if a == TRUE || a == b {
+            return b;
+        }
+        if let Some(&lit) = self.strash.get(&(a, b)) {
+            return lit;
+        }
+        self.ands.push((a, b));
+        let lit = (self.inputs.len() + self.ands.len()) as Lit * 2;
+        self.strash.insert((a, b), lit);
+        lit
+    }
+
+    pub(crate) fn or(&mut self, a: Lit, b: Lit) -> Lit {
+        self.and(a ^ 1, b ^ 1) ^ 1
+    }
+}
+
+// .aag or .blif source --> program text
+pub fn convert(src: &str, filename: &str, nor: bool) -> String {
+    let aig = if filename.ends_with(".aag") {
+        parse_aiger(src)
+    } else {
+        parse_blif(src)
+    };
+    emit(&aig, nor, filename)
+}
+
+fn netlist_error(linenum: usize, msg: &str) -> ! {
+    eprintln!("Netlist error in line {linenum} ({msg})");
+    std::process::exit(1);
+}
+
+// -- AIGER ascii format (aag M I L O A) --
+fn parse_aiger(src: &str) -> Aig {
+    let mut lines = src.lines().enumerate();
+    let header: Vec<usize> = match lines.next() {
+        Some((_, line)) if line.starts_with("aag ") => line[4..]
+            .split_whitespace()
+            .map(|n| n.parse().unwrap_or_else(|_| netlist_error(1, "header")))
+            .collect(),
+        _ => netlist_error(1, "first line: aag M I L O A"),
+    };
+    if header.len() < 5 {
+        netlist_error(1, "first line: aag M I L O A");
+    }
+    let (num_i, num_l, num_o, num_a) = (header[1], header[2], header[3], header[4]);
+    if num_l > 0 {
+        netlist_error(1, "latches are not supported, only combinational netlists");
+    }
+    let mut numbers = |count: usize, width: usize| -> Vec<Vec<u32>> {
+        (0..count)
+            .map(|_| {
+                let (i, line) = lines
+                    .next()
+                    .unwrap_or_else(|| netlist_error(0, "unexpected end"));
+                let nums: Vec<u32> = line
+                    .split_whitespace()
+                    .map(|n| n.parse().unwrap_or_else(|_| netlist_error(i + 1, "number")))
+                    .collect();
+                if nums.len() != width {
+                    netlist_error(i + 1, "wrong number of literals");
+                }
+                nums
+            })
+            .collect()
+    };
+    let input_lits = numbers(num_i, 1);
+    let output_lits = numbers(num_o, 1);
+    let and_lines = numbers(num_a, 3);
+
+    // symbol table: i<n> name, o<n> name, c: comments
+    let mut input_names: Vec<String> = (0..num_i).map(|i| format!("i{i}")).collect();
+    let mut output_names: Vec<String> = (0..num_o).map(|i| format!("o{i}")).collect();
+    for (_, line) in lines {
+        if line == "c" {
+            break;
+        }
+        if let Some((sym, name)) = line.split_once(' ') {
+            let index: usize = sym[1..].parse().unwrap_or(usize::MAX);
+            match &sym[..1] {
+                "i" if index < num_i => input_names[index] = name.to_string(),
+                "o" if index < num_o => output_names[index] = name.to_string(),
+                _ => (),
+            }
+        }
+    }
+
+    // aiger variable --> definition, resolved in topological order
+    let mut aig = Aig::default();
+    let mut map: HashMap<u32, Lit> = HashMap::new();
+    map.insert(0, FALSE);
+    for (lit, name) in input_lits.iter().zip(&input_names) {
+        map.insert(lit[0] / 2, aig.input(name));
+    }
+    let defs: HashMap<u32, (u32, u32)> =
+        and_lines.iter().map(|a| (a[0] / 2, (a[1], a[2]))).collect();
+    fn resolve(
+        lit: u32,
+        aig: &mut Aig,
+        map: &mut HashMap<u32, Lit>,
+        defs: &HashMap<u32, (u32, u32)>,
+        visiting: &mut Vec<u32>,
+    ) -> Lit {
+        let var = lit / 2;
+        if let Some(&l) = map.get(&var) {
+            return l ^ (lit & 1);
+        }
+        let Some(&(a, b)) = defs.get(&var) else {
+            netlist_error(0, &format!("undefined literal {lit}"));
+        };
+        if visiting.contains(&var) {
+            netlist_error(0, &format!("combinational loop at literal {lit}"));
+        }
+        visiting.push(var);
+        let a = resolve(a, aig, map, defs, visiting);
+        let b = resolve(b, aig, map, defs, visiting);
+        visiting.pop();
+        let l = aig.and(a, b);
+        map.insert(var, l);
+        l ^ (lit & 1)
+    }
+    for (lit, name) in output_lits.iter().zip(output_names) {
+        let l = resolve(lit[0], &mut aig, &mut map, &defs, &mut vec![]);
+        aig.outputs.push((name, l));
+    }
+    aig
+}
+
+// -- BLIF: .model .inputs .outputs .names .end --
+fn parse_blif(src: &str) -> Aig {
+    // logical lines: comments removed, '\' continuation joined
+    let mut lines: Vec<(usize, String)> = vec![];
+    let mut continued = false;
+    for (i, line) in src.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        let (line, next_continued) = match line.strip_suffix('\\') {
+            Some(l) => (l, true),
+            None => (line, false),
+        };
+        if continued {
+            let last = lines.last_mut().unwrap();
+            last.1.push(' ');
+            last.1.push_str(line);
+        } else if !line.is_empty() {
+            lines.push((i + 1, line.to_string()));
+        }
+        continued = next_continued;
+    }
+
+    struct Names {
+        linenum: usize,
+        inputs: Vec<String>,
+        cubes: Vec<String>,
+        onset: bool,
+    }
+    let mut inputs = vec![];
+    let mut outputs = vec![];
+    let mut defs: HashMap<String, Names> = HashMap::new();
+    let mut current: Option<String> = None;
+    for (linenum, line) in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words[0].starts_with('.') {
+            current = None;
+        }
+        match words[0] {
+            ".model" | ".end" => (),
+            ".inputs" => inputs.extend(words[1..].iter().map(|w| w.to_string())),
+            ".outputs" => outputs.extend(words[1..].iter().map(|w| w.to_string())),
+            ".names" if words.len() >= 2 => {
+                let out = words[words.len() - 1].to_string();
+                let names = Names {
+                    linenum,
+                    inputs: words[1..words.len() - 1]
+                        .iter()
+                        .map(|w| w.to_string())
+                        .collect(),
+                    cubes: vec![],
+                    onset: true,
+                };
+                if defs.insert(out.clone(), names).is_some() {
+                    netlist_error(linenum, &format!("{out} is defined twice"));
+                }
+                current = Some(out);
+            }
+            ".latch" => netlist_error(
+                linenum,
+                "latches are not supported, only combinational netlists",
+            ),
+            _ if words[0].starts_with('.') => {
+                netlist_error(linenum, &format!("{} is not supported", words[0]))
+            }
+            _ => {
+                let Some(names) = current.as_ref().and_then(|c| defs.get_mut(c)) else {
+                    netlist_error(linenum, "cover line without .names");
+                };
+                let (cube, out) = match words[..] {
+                    [out] if names.inputs.is_empty() => ("", out),
+                    [cube, out] if cube.len() == names.inputs.len() => (cube, out),
+                    _ => netlist_error(linenum, "wrong cover line"),
+                };
+                if !cube.chars().all(|c| "01-".contains(c)) || (out != "0" && out != "1") {
+                    netlist_error(linenum, "wrong cover line");
+                }
+                names.onset = out == "1";
+                names.cubes.push(cube.to_string());
+            }
+        }
+    }
+
+    let mut aig = Aig::default();
+    let mut map: HashMap<String, Lit> = HashMap::new();
+    for name in &inputs {
+        let lit = aig.input(name);
+        map.insert(name.clone(), lit);
+    }
+    fn resolve(
+        name: &str,
+        aig: &mut Aig,
+        map: &mut HashMap<String, Lit>,
+        defs: &HashMap<String, Names>,
+        visiting: &mut Vec<String>,
+    ) -> Lit {
+        if let Some(&l) = map.get(name) {
+            return l;
+        }
+        let Some(names) = defs.get(name) else {
+            netlist_error(0, &format!("undefined signal {name}"));
+        };
+        if visiting.iter().any(|v| v == name) {
+            netlist_error(names.linenum, &format!("combinational loop at {name}"));
+        }
+        visiting.push(name.to_string());
+        let ins: Vec<Lit> = names
+            .inputs
+            .iter()
+            .map(|i| resolve(i, aig, map, defs, visiting))
+            .collect();
+        visiting.pop();
+        // sum of products, the off-set is inverted at the end
+        let mut sum = FALSE;
+        for cube in &names.cubes {
+            let mut product = TRUE;
+            for (c, &lit) in cube.chars().zip(&ins) {
+                match c {
+                    '1' => product = aig.and(product, lit),
+                    '0' => product = aig.and(product, lit ^ 1),
+                    _ => (),
+                }
+            }
+            sum = aig.or(sum, product);
+        }
+        let lit = if names.onset { sum } else { sum ^ 1 };
+        map.insert(name.to_string(), lit);
+        lit
+    }
+    for name in outputs {
+        let lit = resolve(&name, &mut aig, &mut map, &defs, &mut vec![]);
+        aig.outputs.push((name, lit));
+    }
+    aig
+}
+
+// stored bit of a node: value = mem[addr] ^ inv, alt: the inverted copy
+#[derive(Clone, Copy)]
+struct Stored {
+    addr: u8,
+    inv: bool,
+    alt: Option<u8>,
+}
+
+struct Emitter {
+    nor: bool,
+    nodes: Vec<Stored>,
+    free: BTreeSet<u8>,
+    code: Vec<String>,
+}
+
+impl Emitter {
+    // the neutral input: nand(HIGH, x) = nor(LOW, x) = !x
+    fn neutral(&self) -> u8 {
+        if self.nor {
+            LOW
+        } else {
+            HIGH
+        }
+    }
+
+    fn alloc(&mut self) -> u8 {
+        match self.free.pop_first() {
+            Some(addr) => addr,
+            None => {
+                eprintln!("Out of RAM bits (0x00..0x{:02x})", RAM_END - 1);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    fn release(&mut self, node: usize) {
+        let stored = self.nodes[node];
+        if node > 0 {
+            self.free.insert(stored.addr);
+            if let Some(alt) = stored.alt {
+                self.free.insert(alt);
+            }
+        }
+    }
+
+    fn gate(&mut self, dst: u8, a: u8, b: u8, comment: &str) {
+        self.code
+            .push(format!("0x{dst:02x}{a:02x}{b:02x} # {comment}"));
+    }
+
+    // address of a bit with the value of the literal (want_inv: inverted)
+    fn materialize(&mut self, lit: Lit, want_inv: bool) -> u8 {
+        let node = (lit / 2) as usize;
+        let stored = self.nodes[node];
+        if stored.inv == ((lit & 1 == 1) ^ want_inv) {
+            return stored.addr;
+        }
+        if let Some(alt) = stored.alt {
+            return alt;
+        }
+        let alt = self.alloc();
+        self.gate(alt, stored.addr, stored.addr, &format!("not n{node}"));
+        self.nodes[node].alt = Some(alt);
+        alt
+    }
+}
+
+fn emit(aig: &Aig, nor: bool, source: &str) -> String {
+    let first_and = 1 + aig.inputs.len();
+    let end = aig.ands.len(); // outputs: after the last gate
+    let mut last_use = vec![None; first_and + aig.ands.len()];
+    for (i, &(a, b)) in aig.ands.iter().enumerate() {
+        last_use[(a / 2) as usize] = Some(i);
+        last_use[(b / 2) as usize] = Some(i);
+    }
+    for &(_, lit) in &aig.outputs {
+        last_use[(lit / 2) as usize] = Some(end);
+    }
+
+    let const_false = Stored {
+        addr: LOW,
+        inv: false,
+        alt: Some(HIGH),
+    };
+    let mut em = Emitter {
+        nor,
+        nodes: vec![const_false; first_and + aig.ands.len()],
+        free: (0..RAM_END).collect(),
+        code: vec![],
+    };
+    for (i, name) in aig.inputs.iter().enumerate() {
+        let addr = em.alloc();
+        em.gate(addr, em.neutral(), IO, &format!("read {name}"));
+        em.nodes[1 + i] = Stored {
+            addr,
+            inv: true,
+            alt: None,
+        };
+        if last_use[1 + i].is_none() {
+            em.release(1 + i); // unused input: only the stdin bit is consumed
+        }
+    }
+    for (i, &(a, b)) in aig.ands.iter().enumerate() {
+        // nand(a, b) = !(a & b), nor(!a, !b) = a & b
+        let a_addr = em.materialize(a, nor);
+        let b_addr = em.materialize(b, nor);
+        let node = first_and + i;
+        let addr = em.alloc();
+        em.gate(addr, a_addr, b_addr, &format!("n{node} = and({a}, {b})"));
+        em.nodes[node] = Stored {
+            addr,
+            inv: !nor,
+            alt: None,
+        };
+        for n in [(a / 2) as usize, (b / 2) as usize, node] {
+            if last_use[n].is_none_or(|last| last == i) {
+                em.release(n);
+            }
+        }
+    }
+    for (name, lit) in &aig.outputs {
+        let inv = em.materialize(*lit, true);
+        em.gate(IO, em.neutral(), inv, &format!("write {name}"));
+    }
+
+    let cpu_type = if nor { "NOR_CPU" } else { "NAND_CPU" };
+    let mut prog = format!("{cpu_type}       # first line: TYPE of VCPU\n\n");
+    prog.push_str(&format!(
+        "# generated from {source}: {} inputs, {} outputs, {} and gates\n",
+        aig.inputs.len(),
+        aig.outputs.len(),
+        aig.ands.len()
+    ));
+    prog.push_str(&format!("# stdin:  {}\n", aig.inputs.join(" ")));
+    let outputs: Vec<&str> = aig.outputs.iter().map(|(n, _)| n.as_str()).collect();
+    prog.push_str(&format!("# stdout: {}\n\n", outputs.join(" ")));
+    for line in em.code {
+        prog.push_str(&line);
+        prog.push('\n');
+    }
+    prog
+}
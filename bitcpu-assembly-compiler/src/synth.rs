@@ -0,0 +1,760 @@
+// Truth table or sum of products --> NAND (NOR) only assembler macro
+//
+// macro half_adder          # optional, default: the file name
+// inputs a b                # the first input is the MSB of the row number
+// temps tmp tmp2            # optional names of the temporary bits
+// sum = 0110                # truth table of the rows 0..2^n-1, '-': don't care
+// carry = a b               # sum of products: a b' + !a c
+//
+// The gates come from an xor decomposition (f = x ^ g) and a minimized sum of
+// products of the rest. Up to 4 inputs an exact search looks for less gates;
+// when its search budget is exhausted, the result is not proven minimal.
+// The generated macro is run for all input rows and checked against the spec.
+
+use crate::netlist::{Aig, Lit, FALSE, TRUE};
+use std::collections::HashMap;
+
+const EXACT_MAX_INPUTS: usize = 4;
+const SOP_MAX_INPUTS: usize = 10;
+const SEARCH_BUDGET: u64 = 10_000_000; // visited nodes of the exact search, all gate counts
+
+struct Output {
+    name: String,
+    on: Vec<bool>,
+    care: Vec<bool>,
+}
+
+struct Spec {
+    name: String,
+    inputs: Vec<String>,
+    temps: Vec<String>,
+    outputs: Vec<Output>,
+}
+
+fn spec_error(linenum: usize, msg: &str) -> ! {
+    eprintln!("Synth error in line {linenum} ({msg})");
+    std::process::exit(1);
+}
+
+// input i in the row: MSB first
+fn input_bit(row: usize, i: usize, n: usize) -> bool {
+    (row >> (n - 1 - i)) & 1 == 1
+}
+
+// a b' + !a c --> truth table
+fn parse_sop(rhs: &str, inputs: &[String], linenum: usize) -> Vec<bool> {
+    let n = inputs.len();
+    let mut products = vec![];
+    for product in rhs.split('+') {
+        let mut literals = vec![];
+        for lit in product
+            .split([' ', '\t', '&', '*'])
+            .filter(|l| !l.is_empty())
+        {
+            let (name, neg) = if let Some(l) = lit.strip_prefix(['!', '~']) {
+                (l, true)
+            } else if let Some(l) = lit.strip_suffix('\'') {
+                (l, true)
+            } else {
+                (lit, false)
+            };
+            match (name, inputs.iter().position(|i| i == name)) {
+                ("1", _) => (),
+                ("0", _) => literals.push(None), // false product
+                (_, Some(i)) => literals.push(Some((i, neg))),
+                _ => spec_error(linenum, &format!("unknown input {name}")),
+            }
+        }
+        products.push(literals);
+    }
+    (0..1 << n)
+        .map(|row| {
+            products.iter().any(|p| {
+                p.iter().all(|l| match l {
+                    Some((i, neg)) => input_bit(row, *i, n) != *neg,
+                    None => false,
+                })
+            })
+        })
+        .collect()
+}
+
+fn parse_spec(src: &str, default_name: &str) -> Spec {
+    let mut spec = Spec {
+        name: default_name.to_string(),
+        inputs: vec![],
+        temps: vec![],
+        outputs: vec![],
+    };
+    for (i, line) in src.lines().enumerate() {
+        let linenum = i + 1;
+        let line = line.split(['#', ';']).next().unwrap().trim();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            [] => (),
+            ["macro", name] => spec.name = name.to_string(),
+            ["inputs", ..] => spec.inputs = words[1..].iter().map(|w| w.to_string()).collect(),
+            ["temps", ..] => spec.temps = words[1..].iter().map(|w| w.to_string()).collect(),
+            _ => {
+                let Some((name, rhs)) = line.split_once('=') else {
+                    spec_error(linenum, "output = truth table or sum of products");
+                };
+                let (name, rhs) = (name.trim(), rhs.trim());
+                let n = spec.inputs.len();
+                if n == 0 || n > SOP_MAX_INPUTS {
+                    spec_error(
+                        linenum,
+                        &format!("inputs: 1..{SOP_MAX_INPUTS} before the outputs"),
+                    );
+                }
+                let mut names = spec.inputs.iter().chain(&spec.temps);
+                if names.any(|x| x == name) || spec.outputs.iter().any(|o| o.name == name) {
+                    spec_error(linenum, &format!("{name} is used twice"));
+                }
+                let output = if rhs.len() == 1 << n && rhs.chars().all(|c| "01-".contains(c)) {
+                    Output {
+                        name: name.to_string(),
+                        on: rhs.chars().map(|c| c == '1').collect(),
+                        care: rhs.chars().map(|c| c != '-').collect(),
+                    }
+                } else {
+                    Output {
+                        name: name.to_string(),
+                        on: parse_sop(rhs, &spec.inputs, linenum),
+                        care: vec![true; 1 << n],
+                    }
+                };
+                spec.outputs.push(output);
+            }
+        }
+    }
+    if spec.outputs.is_empty() {
+        spec_error(0, "no outputs");
+    }
+    spec
+}
+
+// gate network: signal 0..n: inputs, n + i: gate i
+#[derive(Clone, Copy, PartialEq)]
+enum Source {
+    Signal(usize),
+    Const(bool),
+}
+
+struct Net {
+    gates: Vec<(usize, usize)>,
+    outputs: Vec<Source>,
+}
+
+fn gate_fn(nor: bool, a: u64, b: u64, mask: u64) -> u64 {
+    if nor {
+        !(a | b) & mask
+    } else {
+        !(a & b) & mask
+    }
+}
+
+// -- exact search: iterative deepening over the gate count --
+struct Search<'a> {
+    nor: bool,
+    mask: u64,
+    targets: &'a [(u64, u64)], // on, care
+    funcs: Vec<u64>,           // inputs, then the gates
+    gates: Vec<(usize, usize)>,
+    refs: Vec<u32>,
+    gate_ct: usize,
+    nodes: u64,
+    budget: u64,
+}
+
+impl Search<'_> {
+    fn matches(&self, f: u64, (on, care): (u64, u64)) -> bool {
+        (f ^ on) & care == 0
+    }
+
+    fn dfs(&mut self, n: usize) -> bool {
+        self.nodes += 1;
+        if self.nodes > self.budget {
+            return false;
+        }
+        let depth = self.gates.len();
+        let remaining = self.gate_ct - depth;
+        let unmet = self
+            .targets
+            .iter()
+            .filter(|&&t| !self.funcs[n..].iter().any(|&f| self.matches(f, t)))
+            .count();
+        if unmet > remaining {
+            return false;
+        }
+        // an unused gate must be an output at the end
+        let dangling: Vec<usize> = (0..depth).filter(|&g| self.refs[g] == 0).collect();
+        if dangling.len() > remaining + self.targets.len() {
+            return false;
+        }
+        if remaining == 0 {
+            return dangling.iter().all(|&g| {
+                let f = self.funcs[n + g];
+                self.targets.iter().any(|&t| self.matches(f, t))
+            });
+        }
+        let s = self.funcs.len();
+        let prev = self.gates.last().copied();
+        for b in 0..s {
+            for a in 0..=b {
+                // independent gates only in increasing order
+                if let Some((pa, pb)) = prev {
+                    if a != s - 1 && b != s - 1 && (b, a) <= (pb, pa) {
+                        continue;
+                    }
+                }
+                let f = gate_fn(self.nor, self.funcs[a], self.funcs[b], self.mask);
+                if f == 0 || f == self.mask || self.funcs.contains(&f) {
+                    continue;
+                }
+                self.funcs.push(f);
+                self.gates.push((a, b));
+                self.refs.push(0);
+                for x in [a, b] {
+                    if x >= n {
+                        self.refs[x - n] += 1;
+                    }
+                }
+                if self.dfs(n) {
+                    return true;
+                }
+                for x in [a, b] {
+                    if x >= n {
+                        self.refs[x - n] -= 1;
+                    }
+                }
+                self.refs.pop();
+                self.gates.pop();
+                self.funcs.pop();
+                if self.nodes > self.budget {
+                    return false;
+                }
+            }
+        }
+        false
+    }
+}
+
+// net with less gates than max_gates, None: there is no such net (Ok) or
+// the search budget is exhausted (Err)
+fn exact_search(spec: &Spec, nor: bool, max_gates: usize, budget: u64) -> Result<Option<Net>, ()> {
+    let n = spec.inputs.len();
+    let rows = 1 << n;
+    let mask = if rows == 64 {
+        u64::MAX
+    } else {
+        (1 << rows) - 1
+    };
+    let pack = |bits: &[bool]| (0..rows).fold(0u64, |acc, r| acc | (bits[r] as u64) << r);
+    let inputs: Vec<u64> = (0..n)
+        .map(|i| pack(&(0..rows).map(|r| input_bit(r, i, n)).collect::<Vec<_>>()))
+        .collect();
+    let all: Vec<(u64, u64)> = spec
+        .outputs
+        .iter()
+        .map(|o| (pack(&o.on), pack(&o.care)))
+        .collect();
+    // constant and input outputs are copied, not searched
+    let trivial = |&(on, care): &(u64, u64)| {
+        on & care == 0
+            || (on | !care) & mask == mask
+            || inputs.iter().any(|&f| (f ^ on) & care == 0)
+    };
+    let mut targets: Vec<(u64, u64)> = all.iter().filter(|t| !trivial(t)).copied().collect();
+    targets.dedup();
+
+    let mut nodes = 0;
+    for gate_ct in targets.len().max(1)..max_gates {
+        let mut search = Search {
+            nor,
+            mask,
+            targets: &targets,
+            funcs: inputs.clone(),
+            gates: vec![],
+            refs: vec![],
+            gate_ct,
+            nodes,
+            budget,
+        };
+        if search.dfs(n) {
+            let outputs = all
+                .iter()
+                .map(|&(on, care)| {
+                    if on & care == 0 {
+                        Source::Const(false)
+                    } else if (on | !care) & mask == mask {
+                        Source::Const(true)
+                    } else {
+                        let s = search.funcs.iter().position(|&f| (f ^ on) & care == 0);
+                        Source::Signal(s.unwrap())
+                    }
+                })
+                .collect();
+            return Ok(Some(Net {
+                gates: search.gates,
+                outputs,
+            }));
+        }
+        if search.nodes > budget {
+            return Err(());
+        }
+        nodes = search.nodes;
+    }
+    Ok(None)
+}
+
+// -- heuristic: prime implicants (Quine-McCluskey) and greedy cover --
+// cube: (value, dashes) over the n input bits of the row
+fn prime_implicants(terms: &[usize]) -> Vec<(usize, usize)> {
+    let mut cubes: Vec<(usize, usize)> = terms.iter().map(|&t| (t, 0)).collect();
+    let mut primes = vec![];
+    while !cubes.is_empty() {
+        let mut merged = vec![false; cubes.len()];
+        let mut next = vec![];
+        for i in 0..cubes.len() {
+            for j in i + 1..cubes.len() {
+                let ((v1, d1), (v2, d2)) = (cubes[i], cubes[j]);
+                let diff = v1 ^ v2;
+                if d1 == d2 && diff.count_ones() == 1 {
+                    merged[i] = true;
+                    merged[j] = true;
+                    let cube = (v1 & !diff, d1 | diff);
+                    if !next.contains(&cube) {
+                        next.push(cube);
+                    }
+                }
+            }
+        }
+        for (i, &cube) in cubes.iter().enumerate() {
+            if !merged[i] {
+                primes.push(cube);
+            }
+        }
+        cubes = next;
+    }
+    primes
+}
+
+// f = x ^ g: g is the cofactor x = 0, the cared rows of x = 1 are inverted
+fn xor_cofactor(on: &[bool], care: &[bool], bit: usize) -> Option<(Vec<bool>, Vec<bool>)> {
+    let mut pairs = 0;
+    let mut g = (on.to_vec(), care.to_vec());
+    for r in (0..on.len()).filter(|&r| r & bit == 0) {
+        let s = r | bit;
+        if care[r] && care[s] {
+            if on[r] == on[s] {
+                return None;
+            }
+            pairs += 1;
+        } else if care[s] {
+            g.0[r] = !on[s];
+            g.1[r] = true;
+        }
+        g.0[s] = g.0[r];
+        g.1[s] = g.1[r];
+    }
+    (pairs > 0).then_some(g)
+}
+
+// xor decomposition, then the sum of products of the rest
+fn sop_lit(aig: &mut Aig, on: &[bool], care: &[bool], n: usize, nor: bool) -> Lit {
+    for i in 0..n {
+        if let Some((on, care)) = xor_cofactor(on, care, 1 << (n - 1 - i)) {
+            let x = (1 + i as Lit) * 2;
+            let g = sop_lit(aig, &on, &care, n, nor);
+            // the 4 gate cell: nand(nand(x, t), nand(g, t)), t = nand(x, g),
+            // NOR: the same cell of the inverted x and g
+            let inv = nor as Lit;
+            let (x, g) = (x ^ inv, g ^ inv);
+            let t = aig.and(x, g);
+            let (a, b) = (aig.and(x, t ^ 1), aig.and(g, t ^ 1));
+            return aig.or(a, b) ^ inv;
+        }
+    }
+    let rows = 1 << n;
+    let terms: Vec<usize> = (0..rows).filter(|&r| !care[r] || on[r]).collect();
+    let on: Vec<usize> = (0..rows).filter(|&r| care[r] && on[r]).collect();
+    let primes = prime_implicants(&terms);
+    let covers = |(v, d): (usize, usize), r: usize| r & !d == v;
+    let mut uncovered = on;
+    let mut sum = FALSE;
+    while !uncovered.is_empty() {
+        let &best = primes
+            .iter()
+            .max_by_key(|&&p| uncovered.iter().filter(|&&r| covers(p, r)).count())
+            .unwrap();
+        uncovered.retain(|&r| !covers(best, r));
+        let mut product = TRUE;
+        for i in 0..n {
+            let bit = 1 << (n - 1 - i);
+            if best.1 & bit == 0 {
+                let lit = (1 + i as Lit) * 2;
+                product = aig.and(product, if best.0 & bit != 0 { lit } else { lit ^ 1 });
+            }
+        }
+        sum = aig.or(sum, product);
+    }
+    sum
+}
+
+fn sop_net(spec: &Spec, nor: bool) -> Net {
+    let n = spec.inputs.len();
+    let mut aig = Aig::default();
+    for name in &spec.inputs {
+        aig.input(name);
+    }
+    let lits: Vec<Lit> = spec
+        .outputs
+        .iter()
+        .map(|o| sop_lit(&mut aig, &o.on, &o.care, n, nor))
+        .collect();
+
+    // and-inverter graph --> gates, node: (signal, inverted, inverted copy)
+    let mut gates = vec![];
+    let mut nodes: Vec<(usize, bool, Option<usize>)> = vec![(0, false, None); 1 + n];
+    for (i, node) in nodes.iter_mut().enumerate().skip(1) {
+        *node = (i - 1, false, None);
+    }
+    let materialize = |nodes: &mut Vec<(usize, bool, Option<usize>)>,
+                       gates: &mut Vec<(usize, usize)>,
+                       lit: Lit,
+                       want_inv: bool| {
+        let node = (lit / 2) as usize;
+        let (signal, inv, alt) = nodes[node];
+        if inv == ((lit & 1 == 1) ^ want_inv) {
+            return signal;
+        }
+        if let Some(alt) = alt {
+            return alt;
+        }
+        gates.push((signal, signal));
+        nodes[node].2 = Some(n + gates.len() - 1);
+        n + gates.len() - 1
+    };
+    for &(a, b) in &aig.ands {
+        // nand(a, b) = !(a & b), nor(!a, !b) = a & b
+        let a = materialize(&mut nodes, &mut gates, a, nor);
+        let b = materialize(&mut nodes, &mut gates, b, nor);
+        gates.push((a.min(b), a.max(b)));
+        nodes.push((n + gates.len() - 1, !nor, None));
+    }
+    let outputs = lits
+        .iter()
+        .map(|&lit| match lit {
+            FALSE => Source::Const(false),
+            TRUE => Source::Const(true),
+            _ => Source::Signal(materialize(&mut nodes, &mut gates, lit, false)),
+        })
+        .collect();
+    Net { gates, outputs }
+}
+
+// -- macro code: temps are reused after the last reader --
+#[derive(Clone, Copy)]
+enum Operand {
+    Input(usize),
+    Gate(usize), // instruction index
+    High,
+    Low,
+}
+
+struct Instr {
+    output: Option<usize>,
+    a: Operand,
+    b: Operand,
+}
+
+fn instructions(net: &Net, n: usize) -> Vec<Instr> {
+    let operand = |s: usize| {
+        if s < n {
+            Operand::Input(s)
+        } else {
+            Operand::Gate(s - n)
+        }
+    };
+    let mut claimed = vec![None; net.gates.len()];
+    let mut copies = vec![];
+    for (k, &src) in net.outputs.iter().enumerate() {
+        match src {
+            Source::Signal(s) if s >= n && claimed[s - n].is_none() => claimed[s - n] = Some(k),
+            _ => copies.push((k, src)),
+        }
+    }
+    let mut code: Vec<Instr> = net
+        .gates
+        .iter()
+        .zip(claimed)
+        .map(|(&(a, b), output)| Instr {
+            output,
+            a: operand(a),
+            b: operand(b),
+        })
+        .collect();
+    for (k, src) in copies {
+        match src {
+            // nand(LOW, LOW) = nor(LOW, LOW) = 1, nand(HIGH, HIGH) = nor(HIGH, HIGH) = 0
+            Source::Const(value) => {
+                let c = if value { Operand::Low } else { Operand::High };
+                code.push(Instr {
+                    output: Some(k),
+                    a: c,
+                    b: c,
+                });
+            }
+            // double inversion
+            Source::Signal(s) => {
+                let x = operand(s);
+                code.push(Instr {
+                    output: None,
+                    a: x,
+                    b: x,
+                });
+                let t = Operand::Gate(code.len() - 1);
+                code.push(Instr {
+                    output: Some(k),
+                    a: t,
+                    b: t,
+                });
+            }
+        }
+    }
+    code
+}
+
+// name of every instruction result and the used temps
+fn allocate(spec: &Spec, code: &[Instr]) -> (Vec<String>, Vec<String>) {
+    let mut last_use = vec![0; code.len()];
+    for (i, instr) in code.iter().enumerate() {
+        for op in [instr.a, instr.b] {
+            if let Operand::Gate(g) = op {
+                last_use[g] = i;
+            }
+        }
+    }
+    let mut temp_of = vec![None; code.len()];
+    let mut free: Vec<usize> = vec![];
+    let mut temp_ct = 0;
+    for (i, instr) in code.iter().enumerate() {
+        for op in [instr.a, instr.b] {
+            if let Operand::Gate(g) = op {
+                if let Some(t) = temp_of[g] {
+                    if last_use[g] == i && !free.contains(&t) {
+                        free.push(t);
+                    }
+                }
+            }
+        }
+        if instr.output.is_none() {
+            free.sort_unstable_by(|a, b| b.cmp(a));
+            temp_of[i] = Some(free.pop().unwrap_or_else(|| {
+                temp_ct += 1;
+                temp_ct - 1
+            }));
+        }
+    }
+    if !spec.temps.is_empty() && spec.temps.len() < temp_ct {
+        eprintln!("Synth error: {temp_ct} temps are needed, 'temps' has less");
+        std::process::exit(1);
+    }
+    let temp_name = |t: usize| match spec.temps.get(t) {
+        Some(name) => name.clone(),
+        None => format!("t{t}"),
+    };
+    let names = code
+        .iter()
+        .zip(temp_of)
+        .map(|(instr, temp)| match (instr.output, temp) {
+            (Some(k), _) => spec.outputs[k].name.clone(),
+            (None, t) => temp_name(t.unwrap()),
+        })
+        .collect();
+    (names, (0..temp_ct).map(temp_name).collect())
+}
+
+// run the macro for all input rows
+fn verify(spec: &Spec, code: &[Instr], names: &[String], nor: bool) -> bool {
+    let n = spec.inputs.len();
+    (0..1 << n).all(|row| {
+        let mut mem: HashMap<&str, bool> = HashMap::new();
+        for (i, name) in spec.inputs.iter().enumerate() {
+            mem.insert(name, input_bit(row, i, n));
+        }
+        for (instr, dst) in code.iter().zip(names) {
+            let value = |op: Operand| match op {
+                Operand::Input(i) => mem[spec.inputs[i].as_str()],
+                Operand::Gate(g) => mem[names[g].as_str()],
+                Operand::High => true,
+                Operand::Low => false,
+            };
+            let (a, b) = (value(instr.a), value(instr.b));
+            let result = if nor { !(a | b) } else { !(a & b) };
+            mem.insert(dst, result);
+        }
+        spec.outputs
+            .iter()
+            .all(|o| !o.care[row] || mem[o.name.as_str()] == o.on[row])
+    })
+}
+
+pub fn synthesize(src: &str, filename: &str, default_name: &str, nor: bool) -> String {
+    synthesize_within(src, filename, default_name, nor, SEARCH_BUDGET)
+}
+
+// budget: visited nodes of the exact search
+fn synthesize_within(
+    src: &str,
+    filename: &str,
+    default_name: &str,
+    nor: bool,
+    budget: u64,
+) -> String {
+    let spec = parse_spec(src, default_name);
+    let n = spec.inputs.len();
+    let mut net = sop_net(&spec, nor);
+    let xor = spec
+        .outputs
+        .iter()
+        .any(|o| (0..n).any(|i| xor_cofactor(&o.on, &o.care, 1 << i).is_some()));
+    let heuristic = if xor {
+        "xor decomposition, sum of products"
+    } else {
+        "sum of products"
+    };
+    let mut method = heuristic.to_string();
+    if n <= EXACT_MAX_INPUTS {
+        match exact_search(&spec, nor, net.gates.len(), budget) {
+            Ok(Some(exact)) => {
+                net = exact;
+                method = "minimal by exact search".to_string();
+            }
+            Ok(None) => method = format!("{heuristic}, minimal by exact search"),
+            Err(()) => {
+                method = format!("{heuristic}, not proven minimal: exact search budget exhausted")
+            }
+        }
+    }
+    let code = instructions(&net, n);
+    let (names, temps) = allocate(&spec, &code);
+    if !verify(&spec, &code, &names, nor) {
+        eprintln!("Synth error: verification of {} failed", spec.name);
+        std::process::exit(1);
+    }
+
+    let gate = if nor { "nor" } else { "nand" };
+    let operand_name = |op: Operand| match op {
+        Operand::Input(i) => spec.inputs[i].clone(),
+        Operand::Gate(g) => names[g].clone(),
+        Operand::High => "high".to_string(),
+        Operand::Low => "low".to_string(),
+    };
+    let outputs: Vec<&str> = spec.outputs.iter().map(|o| o.name.as_str()).collect();
+    let mut inc = format!("; {}: synthesized from {filename}\n", spec.name);
+    inc.push_str(&format!(
+        "; {} {gate} gates ({method}), verified for all {} input rows\n",
+        code.len(),
+        1 << n
+    ));
+    inc.push_str(&format!("; inputs: {}\n", spec.inputs.join(" ")));
+    inc.push_str(&format!("; outputs: {}\n", outputs.join(" ")));
+    if !temps.is_empty() {
+        inc.push_str(&format!("; temporary: {}\n", temps.join(" ")));
+    }
+    inc.push_str(&format!("%macro {} 0\n", spec.name));
+    for (instr, dst) in code.iter().zip(&names) {
+        let (a, b) = (operand_name(instr.a), operand_name(instr.b));
+        inc.push_str(&format!("    {dst} = {gate}({a}, {b})\n"));
+    }
+    inc.push_str("%endmacro\n");
+    inc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{synthesize, synthesize_within};
+    use std::collections::HashMap;
+
+    // run the macro body of the generated file, inputs: MSB first of the row
+    fn run(inc: &str, inputs: &[&str], row: usize, nor: bool) -> HashMap<String, bool> {
+        let n = inputs.len();
+        let mut mem: HashMap<String, bool> =
+            HashMap::from([("high".to_string(), true), ("low".to_string(), false)]);
+        for (i, name) in inputs.iter().enumerate() {
+            mem.insert(name.to_string(), (row >> (n - 1 - i)) & 1 == 1);
+        }
+        let gate = if nor { "nor(" } else { "nand(" };
+        for line in inc.lines().filter(|l| l.starts_with("    ")) {
+            let (dst, call) = line.trim().split_once(" = ").unwrap();
+            let args = call.strip_prefix(gate).unwrap().strip_suffix(')').unwrap();
+            let (a, b) = args.split_once(", ").unwrap();
+            let (a, b) = (mem[a], mem[b]);
+            let value = if nor { !(a | b) } else { !(a & b) };
+            mem.insert(dst.to_string(), value);
+        }
+        mem
+    }
+
+    fn gates(inc: &str) -> usize {
+        inc.lines().filter(|l| l.starts_with("    ")).count()
+    }
+
+    #[test]
+    fn half_adder() {
+        let src = include_str!("../sample/half_adder.tt");
+        for nor in [false, true] {
+            let inc = synthesize(src, "half_adder.tt", "half_adder", nor);
+            assert!(inc.contains("%macro half_adder 0"));
+            assert_eq!(gates(&inc), 5);
+            for row in 0..4 {
+                let mem = run(&inc, &["a", "b"], row, nor);
+                let (a, b) = (row >> 1 == 1, row & 1 == 1);
+                assert_eq!(mem["sum"], a ^ b, "row {row}");
+                assert_eq!(mem["carry"], a & b, "row {row}");
+            }
+        }
+    }
+
+    #[test]
+    fn sum_of_products() {
+        // 5 inputs: no exact search
+        let src = "inputs a b c d e\nout = a b + c d e + !a !e\n";
+        let inc = synthesize(src, "test.tt", "sop", false);
+        for row in 0..32 {
+            let mem = run(&inc, &["a", "b", "c", "d", "e"], row, false);
+            let bit = |i: usize| (row >> (4 - i)) & 1 == 1;
+            let expected = bit(0) && bit(1) || bit(2) && bit(3) && bit(4) || !bit(0) && !bit(4);
+            assert_eq!(mem["out"], expected, "row {row}");
+        }
+    }
+
+    #[test]
+    fn xor_4() {
+        let src = "inputs a b c d\ny = 0110100110010110\n";
+        for (nor, max_gates) in [(false, 12), (true, 13)] {
+            let inc = synthesize_within(src, "test.tt", "xor_4", nor, 100_000);
+            assert!(inc.contains("xor decomposition"));
+            assert!(inc.contains("not proven minimal"));
+            assert!(gates(&inc) <= max_gates, "{inc}");
+            for row in 0..16 {
+                let mem = run(&inc, &["a", "b", "c", "d"], row, nor);
+                assert_eq!(mem["y"], (row as u32).count_ones() % 2 == 1, "row {row}");
+            }
+        }
+    }
+
+    #[test]
+    fn dont_care() {
+        let inc = synthesize("inputs a b\ny = 01-0\n", "test.tt", "dc", true);
+        for (row, expected) in [(0, false), (1, true), (3, false)] {
+            assert_eq!(
+                run(&inc, &["a", "b"], row, true)["y"],
+                expected,
+                "row {row}"
+            );
+        }
+    }
+}
@@ -0,0 +1,852 @@
+mod devices;
+mod history;
+
+pub use devices::MemoryMap;
+
+use crate::io::{BitIo, Buffer, Stream};
+use crate::symbols::parse_number;
+use crate::trace::{Step, Tracer};
+use crate::{Cpu, CpuFault, Halt, Stats};
+use history::{Event, History, Undo};
+use std::collections::{HashMap, VecDeque};
+
+pub enum CpuType {
+    Nand,
+    Nor,
+    Xor,
+    Xnor,
+}
+
+// 8 bit dst, 8 bit src1, 8 bit src2
+// by jmp and call addr = src1<<8 + src2
+pub type Instr = (u8, u8, u8);
+
+// base: 0xfc is RAM, call: 0xfc is call/ret
+#[derive(Clone, Copy, PartialEq)]
+pub enum Profile {
+    Base,
+    Call,
+}
+
+// call stack: ret instruction encoding
+// zero: ret = 0xfc0000, every other 0xfc write is a call
+// msb:  ret = 0xfc with SRC1 MSB high, call address 0x0000..0x7fff
+#[derive(Clone, Copy, PartialEq)]
+pub enum Ret {
+    Zero,
+    Msb,
+}
+
+// call stack model
+// unbounded: no limit
+// N:         N return addresses, a call on a full stack faults
+// N:wrap     N return addresses, a call on a full stack overwrites the oldest
+// latch:     one return address, overwritten by a nested call
+#[derive(Clone, Copy, PartialEq)]
+pub enum Stack {
+    Unbounded,
+    Depth(usize),
+    Wrap(usize),
+    Latch,
+}
+
+// stdin bit read at the end of the input
+// fault:       stop the CPU (InputEof)
+// 0, 1:        the bit value
+// status=ADDR: read 0, the RAM address ADDR reads 1 from now on
+#[derive(Clone, Copy, PartialEq)]
+pub enum Eof {
+    Fault,
+    Zero,
+    One,
+    Status(u8),
+}
+
+// Machine settings from the program header, e.g. "NAND_CPU call ret=msb stack=4"
+#[derive(Clone, Copy, Default)]
+pub struct Header {
+    pub profile: Option<Profile>,
+    pub ret: Option<Ret>,
+    pub stack: Option<Stack>,
+}
+
+impl Profile {
+    pub fn parse(value: &str) -> Option<Profile> {
+        match value.to_lowercase().as_str() {
+            "base" => Some(Profile::Base),
+            "call" => Some(Profile::Call),
+            _ => None,
+        }
+    }
+}
+
+impl Ret {
+    pub fn parse(value: &str) -> Option<Ret> {
+        match value.to_lowercase().as_str() {
+            "zero" => Some(Ret::Zero),
+            "msb" => Some(Ret::Msb),
+            _ => None,
+        }
+    }
+}
+
+impl Stack {
+    pub fn parse(value: &str) -> Option<Stack> {
+        let value = value.to_lowercase();
+        let (depth, wrap) = match value.split_once(':') {
+            Some((depth, "wrap")) => (depth, true),
+            Some(_) => return None,
+            None => (value.as_str(), false),
+        };
+        match depth {
+            "unbounded" if !wrap => Some(Stack::Unbounded),
+            "latch" if !wrap => Some(Stack::Latch),
+            _ => match depth.parse() {
+                Ok(0) | Err(_) => None,
+                Ok(n) if wrap => Some(Stack::Wrap(n)),
+                Ok(n) => Some(Stack::Depth(n)),
+            },
+        }
+    }
+
+    // maximum number of return addresses
+    fn capacity(&self) -> Option<usize> {
+        match *self {
+            Stack::Unbounded => None,
+            Stack::Depth(n) | Stack::Wrap(n) => Some(n),
+            Stack::Latch => Some(1),
+        }
+    }
+}
+
+impl Eof {
+    pub fn parse(value: &str) -> Option<Eof> {
+        match value.to_lowercase().as_str() {
+            "fault" => Some(Eof::Fault),
+            "0" => Some(Eof::Zero),
+            "1" => Some(Eof::One),
+            value => {
+                let addr = parse_number(value.strip_prefix("status=")?)?;
+                (addr < 0xfd).then_some(Eof::Status(addr as u8))
+            }
+        }
+    }
+}
+
+// instruction word: dst << 16 | src1 << 8 | src2
+fn parser(value: &str) -> Option<u32> {
+    let inst = parse_number(value)?;
+    (inst <= 0xffffff).then_some(inst as u32)
+}
+
+const HEADER_ERROR: &str = "expected NAND_CPU, NOR_CPU, XOR_CPU or XNOR_CPU [base|call] \
+                            [ret=zero|msb] [stack=unbounded|latch|N|N:wrap]";
+
+// Err: line number, line and what is wrong
+pub fn compiler(src: &str) -> Result<(CpuType, Header, Vec<Instr>), String> {
+    let mut cputype = CpuType::Nand;
+    let mut header = Header::default();
+    let mut prog = vec![];
+    for (i, line) in src.lines().enumerate() {
+        let rowstart = line.split('#').next().unwrap().trim();
+        if rowstart.is_empty() {
+            continue;
+        }
+        let error = |msg: &str| format!("line {}: {rowstart}: {msg}", i + 1);
+        if i == 0 {
+            let mut token = rowstart.split_whitespace();
+            cputype = match token.next().unwrap() {
+                "NAND_CPU" => CpuType::Nand,
+                "NOR_CPU" => CpuType::Nor,
+                "XOR_CPU" => CpuType::Xor,
+                "XNOR_CPU" => CpuType::Xnor,
+                _ => return Err(error(HEADER_ERROR)),
+            };
+            for option in token {
+                if let Some(ret) = option.strip_prefix("ret=") {
+                    header.ret = Some(Ret::parse(ret).ok_or_else(|| error(HEADER_ERROR))?);
+                } else if let Some(stack) = option.strip_prefix("stack=") {
+                    header.stack = Some(Stack::parse(stack).ok_or_else(|| error(HEADER_ERROR))?);
+                } else {
+                    header.profile =
+                        Some(Profile::parse(option).ok_or_else(|| error(HEADER_ERROR))?);
+                }
+            }
+            continue;
+        }
+        let inst = parser(rowstart).ok_or_else(|| error("invalid instruction"))?;
+        prog.push(((inst >> 16) as u8, (inst >> 8) as u8, inst as u8));
+    }
+    Ok((cputype, header, prog))
+}
+
+// line number (from 1) of every instruction in the program file
+pub fn source_lines(src: &str) -> Vec<usize> {
+    src.lines()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, line)| !line.split('#').next().unwrap().trim().is_empty())
+        .map(|(i, _)| i + 1)
+        .collect()
+}
+
+// machine state of loop detection: pc, RAM, call stack, index register and
+// jump latch
+#[derive(PartialEq, Eq, Hash)]
+struct State {
+    pc: usize,
+    data: [bool; 256],
+    pc_save: Vec<usize>,
+    index: u8,
+    latch: u16,
+}
+
+// -- VCPU Runner --
+pub struct Vcpu<I: BitIo = Stream> {
+    cputype: CpuType,
+    profile: Profile,
+    ret: Ret,
+    stack: Stack,
+    pub(crate) prog: Vec<Instr>,
+    pub(crate) pc: usize,
+    pub(crate) pc_save: Vec<usize>, // call stack, top: last
+    pub(crate) data: [bool; 256],
+    pub(crate) index: u8,            // index register (--device index@ADDR)
+    pub(crate) latch: u16,           // jump target (--device jump@ADDR)
+    pub io: I,                       // stdin/stdout device
+    pub stdin_queue: VecDeque<bool>, // read before stdin
+    pub(crate) stdout_capture: Option<Buffer<bool>>, // instead of stdout, e.g. gdb stub
+    history: Option<History>,        // undo log for reverse execution
+    pub(crate) fault: Option<CpuFault>, // why the last step failed
+    pub trace: bool,
+    pub tracers: Vec<Box<dyn Tracer<I>>>, // trace sinks, e.g. VCD
+    pub max_steps: Option<u64>,
+    pub detect_loop: bool, // stop when pc + RAM + call stack repeats without I/O
+    pub eof: Eof,
+    pub map: MemoryMap,  // RAM size and peripherals
+    device_reads: u64,   // timer and random bits, for loop detection
+    eof_at: Option<u64>, // stdin bits read before the end of the input
+    pub stats: Stats,
+}
+
+impl<I: BitIo> Vcpu<I> {
+    pub fn new(
+        cputype: CpuType,
+        prog: Vec<Instr>,
+        profile: Profile,
+        ret: Ret,
+        stack: Stack,
+        io: I,
+    ) -> Self {
+        let data = [false; 256];
+        Vcpu {
+            cputype,
+            profile,
+            ret,
+            stack,
+            prog,
+            pc: 0,
+            pc_save: vec![],
+            data,
+            index: 0,
+            latch: 0,
+            io,
+            stdin_queue: VecDeque::new(),
+            stdout_capture: None,
+            history: None,
+            fault: None,
+            trace: false,
+            tracers: vec![],
+            max_steps: None,
+            detect_loop: false,
+            eof: Eof::Fault,
+            map: MemoryMap::default(),
+            device_reads: 0,
+            eof_at: None,
+            stats: Stats::default(),
+        }
+    }
+
+    fn trace_print(&self, pc: usize, dst: u8, src1: u8, src2: u8) {
+        if self.trace {
+            let mut tracemem = String::new();
+            for (i, &dbool) in self.data[0..0x80].iter().enumerate() {
+                if i % 4 == 0 {
+                    tracemem.push(' ');
+                }
+                if i % 8 == 0 {
+                    tracemem.push(' ');
+                }
+                let d = 0x30 + dbool as u8;
+                tracemem.push(d as char);
+            }
+            eprintln!("{pc:04x}: {dst:02x}, {src1:02x}, {src2:02x} mem:{tracemem}");
+        }
+    }
+
+    fn trace_print_jmp(&self, call: bool) {
+        if self.trace {
+            let tracemsg = if call { "call" } else { "jmp" };
+            eprintln!("--- {tracemsg} ---");
+        }
+    }
+
+    fn trace_print_ret(&self) {
+        if self.trace {
+            eprintln!("--- ret ---");
+        }
+    }
+
+    fn io_getbit(&mut self) -> bool {
+        self.stats.stdin += 1;
+        if let Some(bit) = self.replay_bit() {
+            return bit;
+        }
+        let bit = match self.stdin_queue.pop_front() {
+            Some(bit) => bit,
+            None => match (self.io.read_bit(), self.eof) {
+                (Some(bit), _) => bit,
+                (None, Eof::Fault) => {
+                    self.fault = Some(CpuFault::InputEof);
+                    return false;
+                }
+                (None, eof) => {
+                    self.eof_at.get_or_insert(self.stats.stdin - 1);
+                    eof == Eof::One
+                }
+            },
+        };
+        self.log_bit(bit);
+        bit
+    }
+
+    fn io_putbit(&mut self, value: bool) {
+        self.stats.stdout += 1;
+        if !self.new_output() {
+            return; // reverse execution: written before
+        }
+        match &mut self.stdout_capture {
+            Some(capture) => capture.write_bit(value),
+            None => self.io.write_bit(value),
+        }
+    }
+
+    // Memory & memory mapped functions
+    fn mem_rd(&mut self, addr: u8) -> bool {
+        match addr {
+            _ if self.eof == Eof::Status(addr) => {
+                // end of stdin status
+                self.eof_at.is_some_and(|bits| self.stats.stdin > bits)
+            }
+            0x00..=0xfc => self.device_rd(addr), // generic RAM, peripherals
+            0xfd => self.io_getbit(),            // stdin  - Read stdin,
+            0xfe => false,                       // const GND
+            0xff => true,                        // const +3v3
+        }
+    }
+
+    // Memory & memory mapped functions
+    fn mem_wr(&mut self, addr: u8, value: bool) {
+        match addr {
+            0xfd => self.io_putbit(value),              // stdout
+            0x00..=0xfc => self.device_wr(addr, value), // RAM, peripherals
+            _ => self.data[addr as usize] = value,      // skip, jmp
+        }
+    }
+
+    // jmp, call or ret instruction: (call, ret), also call [latch]
+    fn control(&self, dst: u8, src1: u8, src2: u8) -> (bool, bool) {
+        let callret = self.profile == Profile::Call && dst == 0xfc;
+        let ret = callret
+            && match self.ret {
+                Ret::Zero => src1 == 0x00 && src2 == 0,
+                Ret::Msb => src1 & 0x80 != 0,
+            };
+        (callret && !ret || self.via_latch(dst) == Some(true), ret)
+    }
+
+    // false: RAM address without RAM (--ram), 0xfc of the call profile: call/ret
+    fn is_mapped(&self, addr: u8) -> bool {
+        self.is_accessible(addr) || self.profile == Profile::Call && addr == 0xfc
+    }
+
+    // jmp, call, ret: src1, src2 are the target, not addresses
+    pub(crate) fn is_immediate(&self, dst: u8) -> bool {
+        dst == 0xff || self.profile == Profile::Call && dst == 0xfc
+    }
+
+    // stdin bits read by the instruction
+    pub(crate) fn stdin_reads(&self, pc: usize) -> usize {
+        let (dst, src1, src2) = self.prog[pc];
+        if self.is_immediate(dst) {
+            return 0;
+        }
+        (src1 == 0xfd) as usize + (src2 == 0xfd) as usize
+    }
+
+    pub(crate) fn is_call(&self, pc: usize) -> bool {
+        let (dst, src1, src2) = self.prog[pc];
+        self.control(dst, src1, src2).0
+    }
+
+    // instruction in assembler like form, e.g. "0x10 = nand(0xff, 0xfd)"
+    pub(crate) fn disasm(&self, pc: usize) -> String {
+        let (dst, src1, src2) = self.prog[pc];
+        let (call, ret) = self.control(dst, src1, src2);
+        let target = (src1 as usize) << 8 | src2 as usize;
+        let gate = match self.cputype {
+            CpuType::Nand => "nand",
+            CpuType::Nor => "nor",
+            CpuType::Xor => "xor",
+            CpuType::Xnor => "xnor",
+        };
+        if let Some(call) = self.via_latch(dst) {
+            let op = if call { "call" } else { "jmp" };
+            let latch = dst - 1 - call as u8;
+            return format!("{op} [0x{latch:02x}]");
+        }
+        match dst {
+            0xff => format!("jmp 0x{target:04x}"),
+            _ if call => format!("call 0x{target:04x}"),
+            _ if ret => "ret".to_string(),
+            0xfe => format!("skip_{gate}(0x{src1:02x}, 0x{src2:02x})"),
+            _ => format!("0x{dst:02x} = {gate}(0x{src1:02x}, 0x{src2:02x})"),
+        }
+    }
+
+    fn state(&self) -> State {
+        State {
+            pc: self.pc,
+            data: self.data,
+            pc_save: self.pc_save.clone(),
+            index: self.index,
+            latch: self.latch,
+        }
+    }
+
+    // end of the run: trace sinks write their files, stdout is flushed
+    pub fn finish_trace(&mut self) {
+        self.io.flush();
+        let mut tracers = std::mem::take(&mut self.tracers);
+        for tracer in tracers.iter_mut() {
+            tracer.finish(self);
+        }
+        self.tracers = tracers;
+    }
+
+    // state at the end of an interactive run (debugger, gdb stub): the
+    // fault of the last step, else the end
+    pub(crate) fn halt(&self) -> Halt {
+        match self.fault {
+            Some(fault) => Halt::Fault {
+                fault,
+                pc: self.pc,
+                step: self.stats.steps + 1,
+            },
+            None => Halt::End,
+        }
+    }
+
+    pub fn runner(&mut self) -> Halt {
+        // CPU run
+        // loop detection: states and pc trail since the last I/O event
+        let mut seen: HashMap<State, usize> = HashMap::new();
+        let mut trail: Vec<usize> = vec![];
+        let mut io_events = 0;
+        loop {
+            if self.pc >= self.prog.len() {
+                return Halt::End;
+            }
+            if self.max_steps.is_some_and(|max| self.stats.steps >= max) {
+                return Halt::MaxSteps;
+            }
+            if self.detect_loop {
+                // after the first read at the end of stdin the read bits are
+                // constant, not an I/O event
+                let stdin = match self.eof_at {
+                    Some(bits) => self.stats.stdin.min(bits + 1),
+                    None => self.stats.stdin,
+                };
+                let events = stdin + self.stats.stdout + self.device_reads;
+                if io_events != events {
+                    io_events = events;
+                    seen.clear();
+                    trail.clear();
+                }
+                let state = self.state();
+                if let Some(&start) = seen.get(&state) {
+                    let body = &trail[start..];
+                    return Halt::Loop {
+                        pc: self.pc,
+                        first: *body.iter().min().unwrap(),
+                        last: *body.iter().max().unwrap(),
+                    };
+                }
+                seen.insert(state, trail.len());
+                trail.push(self.pc);
+            }
+            if !self.step() {
+                if let Some(fault) = self.fault {
+                    return Halt::Fault {
+                        fault,
+                        pc: self.pc,
+                        step: self.stats.steps + 1,
+                    };
+                }
+            }
+        }
+    }
+}
+
+impl<I: BitIo> Cpu for Vcpu<I> {
+    type Word = bool;
+
+    fn step(&mut self) -> bool {
+        if self.pc >= self.prog.len() {
+            return false;
+        }
+        let (dst, src1, src2) = self.prog[self.pc];
+        // faults: the instruction is not executed, pc stays
+        self.fault = None;
+        let (call, ret) = self.control(dst, src1, src2);
+        if ret && self.pc_save.is_empty() {
+            self.fault = Some(CpuFault::StackUnderflow);
+            return false;
+        }
+        let immediate = self.is_immediate(dst);
+        let operands = if immediate {
+            &[dst][..]
+        } else {
+            &[dst, src1, src2]
+        };
+        if operands.iter().any(|&addr| !self.is_mapped(addr)) {
+            self.fault = Some(CpuFault::Unmapped);
+            return false;
+        }
+        let full = self.stack.capacity() == Some(self.pc_save.len());
+        if call && full && matches!(self.stack, Stack::Depth(_)) {
+            self.fault = Some(CpuFault::StackOverflow);
+            return false;
+        }
+        self.trace_print(self.pc, dst, src1, src2); // trace for debug
+        let stdin = self.stats.stdin;
+        let target = self.effective(dst);
+        let mut undo = Undo {
+            pc: self.pc,
+            write: (dst != 0xfd).then_some((target, self.data[target as usize])),
+            index: self.index,
+            latch: self.latch,
+            event: Event::None,
+            stdin: 0,
+            stdout: dst == 0xfd,
+            device_reads: self.device_reads,
+            eof_at: self.eof_at,
+        };
+
+        // ALU func
+        let (a, b) = if immediate {
+            (false, false)
+        } else {
+            (self.mem_rd(src1), self.mem_rd(src2))
+        };
+        if self.fault.is_some() {
+            self.stats.stdin = stdin; // logged bits are replayed
+            self.device_reads = undo.device_reads;
+            return false;
+        }
+        self.stats.steps += 1;
+        let result = match self.cputype {
+            CpuType::Nand => !(a & b),
+            CpuType::Nor => !(a | b),
+            CpuType::Xor => a ^ b,
+            CpuType::Xnor => !(a ^ b),
+        };
+        self.mem_wr(dst, result);
+        // SKIP next instruction
+        if dst == 0xfe && result {
+            self.stats.skips += 1;
+            undo.event = Event::Skip;
+            self.pc += 1;
+        }
+        // normal increment PC
+        self.pc += 1;
+        // JMP and call function, immediate or latched target
+        let via = self.via_latch(dst);
+        if dst == 0xff || call || via.is_some() {
+            self.trace_print_jmp(call); // trace for debug
+            if call {
+                self.stats.calls += 1;
+                // wrap, latch: the oldest return address is overwritten
+                let lost = full.then(|| self.pc_save.remove(0));
+                undo.event = Event::Call(lost);
+                self.pc_save.push(self.pc);
+            } else {
+                self.stats.jumps += 1;
+                undo.event = Event::Jump;
+            }
+            self.pc = match via {
+                Some(_) => self.latch as usize,
+                None => (src1 as usize) << 8 | src2 as usize,
+            };
+        }
+        // return function
+        if ret {
+            self.stats.rets += 1;
+            self.pc = self.pc_save.pop().unwrap(); // checked: not empty
+            undo.event = Event::Ret(self.pc);
+            self.trace_print_ret();
+        }
+        undo.stdin = (self.stats.stdin - stdin) as u8;
+        if !self.tracers.is_empty() {
+            let step = Step {
+                step: self.stats.steps,
+                pc: undo.pc,
+                instr: (dst, src1, src2),
+                write: target,
+                src: (a, b),
+                old: undo.write.is_some_and(|(_, old)| old),
+                new: self.data[target as usize],
+                result,
+                skip: undo.event == Event::Skip,
+                jump: undo.event == Event::Jump,
+                call,
+                ret,
+                next_pc: self.pc,
+            };
+            let mut tracers = std::mem::take(&mut self.tracers);
+            for tracer in tracers.iter_mut() {
+                tracer.step(self, &step);
+            }
+            self.tracers = tracers;
+        }
+        self.log_step(undo);
+        true
+    }
+
+    fn run(&mut self) {
+        self.runner();
+    }
+
+    fn pc(&self) -> usize {
+        self.pc
+    }
+
+    fn memory(&self) -> &[bool] {
+        &self.data
+    }
+
+    fn reset(&mut self) {
+        self.pc = 0;
+        self.pc_save.clear();
+        self.data = [false; 256];
+        self.index = 0;
+        self.latch = 0;
+        self.stats = Stats::default();
+        self.stdin_queue.clear();
+        self.fault = None;
+        self.eof_at = None;
+        if self.history.is_some() {
+            self.record();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vcpu(prog: Vec<Instr>) -> Vcpu<Buffer<bool>> {
+        let io = Buffer::default();
+        Vcpu::new(
+            CpuType::Nand,
+            prog,
+            Profile::Call,
+            Ret::Zero,
+            Stack::Unbounded,
+            io,
+        )
+    }
+
+    #[test]
+    fn header() {
+        let (_, header, prog) = compiler("NAND_CPU call ret=msb stack=4:wrap\n0xff0000\n").unwrap();
+        assert!(header.profile == Some(Profile::Call));
+        assert!(header.ret == Some(Ret::Msb));
+        assert!(header.stack == Some(Stack::Wrap(4)));
+        assert_eq!(prog, vec![(0xff, 0x00, 0x00)]);
+    }
+
+    #[test]
+    fn compiler_errors() {
+        let error = |src: &str| compiler(src).err().unwrap();
+        assert_eq!(
+            error("NAND_CPU\n0xff0000\n\n0xzz  # comment\n"),
+            "line 4: 0xzz: invalid instruction"
+        );
+        assert_eq!(
+            error("NAND_CPU\n0x1000000\n"),
+            "line 2: 0x1000000: invalid instruction"
+        );
+        assert!(error("NAND_CPU stack=0\n").starts_with("line 1: NAND_CPU stack=0: expected"));
+        assert!(error("AND_CPU\n").starts_with("line 1: AND_CPU: expected NAND_CPU"));
+    }
+
+    #[test]
+    fn base_profile() {
+        // 0xfc is RAM: 0xfc = 1, 0x00 = !0xfc
+        let mut vcpu = vcpu(vec![(0xfc, 0xfe, 0xfe), (0x00, 0xfc, 0xff)]);
+        vcpu.profile = Profile::Base;
+        vcpu.data[0x00] = true;
+        assert_eq!(vcpu.runner(), Halt::End);
+        assert!(vcpu.data[0xfc] && !vcpu.data[0x00]);
+        assert_eq!(vcpu.stats.calls, 0);
+    }
+
+    #[test]
+    fn call_and_ret() {
+        // call 0x0002, jmp 0x0004, 0x00 = 1, ret
+        let ret_zero = (0xfc, 0x00, 0x00);
+        let ret_msb = (0xfc, 0x80, 0x00);
+        for (ret, instr) in [(Ret::Zero, ret_zero), (Ret::Msb, ret_msb)] {
+            let prog = vec![
+                (0xfc, 0x00, 0x02),
+                (0xff, 0x00, 0x04),
+                (0x00, 0xfe, 0xfe),
+                instr,
+            ];
+            let mut vcpu = vcpu(prog);
+            vcpu.ret = ret;
+            assert_eq!(vcpu.runner(), Halt::End);
+            assert!(vcpu.data[0x00]);
+            assert_eq!((vcpu.stats.calls, vcpu.stats.rets), (1, 1));
+        }
+    }
+
+    #[test]
+    fn stack_parse() {
+        assert!(Stack::parse("unbounded") == Some(Stack::Unbounded));
+        assert!(Stack::parse("Latch") == Some(Stack::Latch));
+        assert!(Stack::parse("4") == Some(Stack::Depth(4)));
+        assert!(Stack::parse("4:wrap") == Some(Stack::Wrap(4)));
+        for value in ["0", "0:wrap", "latch:wrap", "4:x", "x"] {
+            assert!(Stack::parse(value).is_none(), "{value}");
+        }
+    }
+
+    // call 0x0001, call 0x0002, call 0x0003, ret, ret
+    fn nested_calls() -> Vec<Instr> {
+        let ret = (0xfc, 0x00, 0x00);
+        vec![
+            (0xfc, 0x00, 0x01),
+            (0xfc, 0x00, 0x02),
+            (0xfc, 0x00, 0x03),
+            ret,
+            ret,
+        ]
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let mut vcpu = vcpu(nested_calls());
+        vcpu.stack = Stack::Depth(2);
+        let halt = vcpu.runner();
+        let fault = CpuFault::StackOverflow;
+        assert_eq!(
+            halt,
+            Halt::Fault {
+                fault,
+                pc: 2,
+                step: 3
+            }
+        );
+        assert_eq!(vcpu.pc_save, vec![1, 2]);
+    }
+
+    #[test]
+    fn stack_wrap() {
+        let mut vcpu = vcpu(nested_calls());
+        vcpu.stack = Stack::Wrap(2);
+        for _ in 0..3 {
+            vcpu.step();
+        }
+        assert_eq!(vcpu.pc_save, vec![2, 3]); // 1 is overwritten
+        vcpu.step();
+        vcpu.step();
+        assert_eq!(vcpu.pc, 2);
+        assert!(vcpu.pc_save.is_empty());
+    }
+
+    #[test]
+    fn stack_latch() {
+        let mut vcpu = vcpu(nested_calls());
+        vcpu.stack = Stack::Latch;
+        vcpu.step();
+        vcpu.step();
+        assert_eq!(vcpu.pc_save, vec![2]);
+        // call 0x0003, ret, ret: the second ret has no return address
+        let halt = vcpu.runner();
+        let fault = CpuFault::StackUnderflow;
+        assert_eq!(
+            halt,
+            Halt::Fault {
+                fault,
+                pc: 3,
+                step: 5
+            }
+        );
+    }
+
+    #[test]
+    fn max_steps() {
+        let mut vcpu = vcpu(vec![(0xff, 0x00, 0x00)]); // jmp 0x0000
+        vcpu.max_steps = Some(5);
+        assert_eq!(vcpu.runner(), Halt::MaxSteps);
+        assert_eq!((vcpu.stats.steps, vcpu.stats.jumps), (5, 5));
+    }
+
+    #[test]
+    fn stats() {
+        // stdout = !stdin, skip, (skipped), 0x00 = 1
+        let prog = vec![
+            (0xfd, 0xfd, 0xff),
+            (0xfe, 0xfe, 0xfe),
+            (0x00, 0xff, 0xff),
+            (0x01, 0xfe, 0xfe),
+        ];
+        let mut vcpu = vcpu(prog);
+        vcpu.io.input.push_back(true);
+        assert_eq!(vcpu.runner(), Halt::End);
+        assert_eq!(vcpu.io.output, vec![false]);
+        assert!(!vcpu.data[0x00] && vcpu.data[0x01]);
+        let report = vcpu.stats.report("bits");
+        assert_eq!(
+            report,
+            "instructions: 3\njumps: 0\ncalls: 0\nrets: 0\nskips: 1\nstdin bits: 1\nstdout bits: 1"
+        );
+    }
+
+    #[test]
+    fn detect_loop() {
+        // toggle RAM 0x00, jmp 0x0000: the state repeats
+        let mut vcpu = vcpu(vec![(0x00, 0x00, 0x00), (0xff, 0x00, 0x00)]);
+        vcpu.detect_loop = true;
+        let halt = vcpu.runner();
+        assert!(matches!(
+            halt,
+            Halt::Loop {
+                pc: 0,
+                first: 0,
+                last: 1
+            }
+        ));
+    }
+
+    #[test]
+    fn no_loop() {
+        // toggle RAM 0x00, skip the jmp 0x0000 when it is 0 again
+        let prog = vec![(0x00, 0x00, 0x00), (0xfe, 0x00, 0xff), (0xff, 0x00, 0x00)];
+        let mut vcpu = vcpu(prog);
+        vcpu.detect_loop = true;
+        assert!(matches!(vcpu.runner(), Halt::End));
+    }
+}
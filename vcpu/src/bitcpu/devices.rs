@@ -0,0 +1,430 @@
+// Memory map of the bit CPU: RAM size and peripherals at RAM addresses
+//
+//   --ram N            RAM bits 0x00..N, other RAM addresses are unmapped
+//   cycle@ADDR[:N]     timer: reads bit 0 of (executed instructions / N)
+//   rng@ADDR[:SEED]    pseudo-random bit, same sequence for the same seed
+//   led@ADDR[:COUNT]   COUNT (8) LED bits, shown on stderr when changed
+//   seg7@ADDR          7-segment digit, bits a, b, c, d, e, f, g, dp at
+//                      ADDR..ADDR+7, shown on stderr when dp is written
+//   index@ADDR         index register: a write to ADDR shifts the bit into
+//                      the 8 bit pointer (pointer << 1 | bit), a read gives
+//                      the pointer MSB; ADDR+1 reads and writes the RAM bit
+//                      the pointer selects
+//   jump@ADDR          jump latch: a write to ADDR shifts the bit into the
+//                      16 bit target (target << 1 | bit), a read gives the
+//                      target MSB; a write to ADDR+1 jumps to the target,
+//                      a write to ADDR+2 calls it (jmp [ADDR], call [ADDR]),
+//                      ADDR+2 is unmapped in the base profile (no ret)
+//
+// LED and segment bits are RAM bits, readable and restored by reverse
+// execution. Timer and random bits depend on the step count only. The
+// pointer and the jump target are in the undo log.
+
+use super::{Profile, Vcpu};
+use crate::io::BitIo;
+use crate::symbols::parse_number;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Cell {
+    Ram,
+    Unmapped,
+    Cycle(u64),  // instructions per half period
+    Random(u64), // seed
+    Led,
+    Segment,
+    Index,
+    Window, // RAM bit selected by the index register
+    Latch,
+    JumpVia, // jmp to the latched target
+    CallVia, // call of the latched target
+}
+
+#[derive(Clone)]
+pub struct MemoryMap {
+    cells: [Cell; 256],
+    leds: Vec<(u8, u8)>, // address, count
+    digits: Vec<u8>,     // address of the a segment
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        MemoryMap {
+            cells: [Cell::Ram; 256],
+            leds: vec![],
+            digits: vec![],
+        }
+    }
+}
+
+// splitmix64
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+impl MemoryMap {
+    // RAM bits 0x00..ram
+    pub fn with_ram(ram: usize) -> Result<Self, String> {
+        if ram > 0xfd {
+            return Err(format!("RAM size {ram}: at most 253 bits"));
+        }
+        let mut map = MemoryMap::default();
+        for cell in &mut map.cells[ram..0xfd] {
+            *cell = Cell::Unmapped;
+        }
+        Ok(map)
+    }
+
+    // false: unmapped or a peripheral
+    pub fn is_ram(&self, addr: u8) -> bool {
+        self.cells[addr as usize] == Cell::Ram
+    }
+
+    // device: KIND@ADDR[:ARG]
+    pub fn add(&mut self, spec: &str) -> Result<(), String> {
+        let error = || format!("invalid device: {spec}");
+        let (kind, place) = spec.split_once('@').ok_or_else(error)?;
+        let (addr, arg) = match place.split_once(':') {
+            Some((addr, arg)) => (addr, Some(parse_number(arg).ok_or_else(error)?)),
+            None => (place, None),
+        };
+        let addr = parse_number(addr).ok_or_else(error)?;
+        let (cell, count) = match kind {
+            "cycle" => (Cell::Cycle(arg.unwrap_or(1).max(1) as u64), 1),
+            "rng" => (Cell::Random(arg.unwrap_or(1) as u64), 1),
+            "led" => (Cell::Led, arg.unwrap_or(8)),
+            "seg7" if arg.is_none() => (Cell::Segment, 8),
+            "index" if arg.is_none() => (Cell::Index, 2),
+            "jump" if arg.is_none() => (Cell::Latch, 3),
+            _ => return Err(error()),
+        };
+        if count == 0 || addr.checked_add(count).is_none_or(|end| end > 0xfd) {
+            return Err(format!("{spec}: addresses out of 0x00..0xfc"));
+        }
+        for a in addr..addr + count {
+            if !matches!(self.cells[a], Cell::Ram | Cell::Unmapped) {
+                return Err(format!("{spec}: address 0x{a:02x} already mapped"));
+            }
+            self.cells[a] = cell;
+        }
+        match cell {
+            Cell::Index => self.cells[addr + 1] = Cell::Window,
+            Cell::Latch => {
+                self.cells[addr + 1] = Cell::JumpVia;
+                self.cells[addr + 2] = Cell::CallVia;
+            }
+            _ => (),
+        }
+        match cell {
+            Cell::Led => self.leds.push((addr as u8, count as u8)),
+            Cell::Segment => self.digits.push(addr as u8),
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+impl<I: BitIo> Vcpu<I> {
+    // accessed bit of an address: the index window selects a RAM bit
+    pub(crate) fn effective(&self, addr: u8) -> u8 {
+        match self.map.cells[addr as usize] {
+            Cell::Window => self.index,
+            _ => addr,
+        }
+    }
+
+    // write of dst jumps to the latched target: Some(call)
+    pub(super) fn via_latch(&self, dst: u8) -> Option<bool> {
+        match self.map.cells[dst as usize] {
+            Cell::JumpVia => Some(false),
+            Cell::CallVia => Some(true),
+            _ => None,
+        }
+    }
+
+    // false: unmapped, the index window selects no RAM bit, or call [latch]
+    // in the base profile
+    pub(super) fn is_accessible(&self, addr: u8) -> bool {
+        match self.map.cells[addr as usize] {
+            Cell::Unmapped => false,
+            Cell::CallVia => self.profile == Profile::Call,
+            Cell::Window => {
+                let bit = self.index as usize;
+                bit < 0xfd && matches!(self.map.cells[bit], Cell::Ram | Cell::Led | Cell::Segment)
+            }
+            _ => true,
+        }
+    }
+}
+
+impl<I: BitIo> Vcpu<I> {
+    // read of the RAM address range
+    pub(super) fn device_rd(&mut self, addr: u8) -> bool {
+        match self.map.cells[addr as usize] {
+            Cell::Cycle(n) => {
+                self.device_reads += 1;
+                (self.stats.steps / n) & 1 != 0
+            }
+            Cell::Random(seed) => {
+                self.device_reads += 1;
+                mix(seed ^ mix(self.stats.steps)) & 1 != 0
+            }
+            Cell::Index => self.index & 0x80 != 0,
+            Cell::Window => self.data[self.index as usize],
+            Cell::Latch => self.latch & 0x8000 != 0,
+            Cell::JumpVia | Cell::CallVia => false,
+            _ => self.data[addr as usize],
+        }
+    }
+
+    // write of the RAM address range
+    pub(super) fn device_wr(&mut self, addr: u8, value: bool) {
+        let old = self.data[addr as usize];
+        match self.map.cells[addr as usize] {
+            Cell::Cycle(_) | Cell::Random(_) => (), // read only
+            Cell::Index => self.index = self.index << 1 | value as u8,
+            Cell::Window => self.device_wr(self.index, value),
+            Cell::Latch => self.latch = self.latch << 1 | value as u16,
+            Cell::JumpVia | Cell::CallVia => (), // jump in step()
+            Cell::Led if old != value => {
+                self.data[addr as usize] = value;
+                self.show_leds();
+            }
+            Cell::Segment if self.map.digits.iter().any(|&a| addr == a + 7) => {
+                self.data[addr as usize] = value;
+                self.show_digits();
+            }
+            _ => self.data[addr as usize] = value,
+        }
+    }
+
+    fn show_leds(&mut self) {
+        let banks: Vec<String> = self
+            .map
+            .leds
+            .iter()
+            .map(|&(addr, count)| {
+                let bits = &self.data[addr as usize..(addr + count) as usize];
+                bits.iter().map(|&on| if on { '●' } else { '○' }).collect()
+            })
+            .collect();
+        self.io.flush();
+        eprintln!("led: {}", banks.join(" "));
+    }
+
+    fn show_digits(&mut self) {
+        let mut lines = [String::new(), String::new(), String::new()];
+        for &addr in &self.map.digits {
+            let segment = |n: u8, c: char| {
+                if self.data[(addr + n) as usize] {
+                    c
+                } else {
+                    ' '
+                }
+            };
+            lines[0].extend([' ', segment(0, '_'), ' ', ' ']);
+            lines[1].extend([segment(5, '|'), segment(6, '_'), segment(1, '|'), ' ']);
+            lines[2].extend([
+                segment(4, '|'),
+                segment(3, '_'),
+                segment(2, '|'),
+                segment(7, '.'),
+            ]);
+        }
+        self.io.flush();
+        for line in lines {
+            eprintln!("{}", line.trim_end());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcpu::{CpuType, Instr, Ret, Stack};
+    use crate::io::Buffer;
+    use crate::{CpuFault, Halt};
+
+    // 32 RAM bits and the devices
+    fn vcpu(prog: Vec<Instr>, devices: &[&str]) -> Vcpu<Buffer<bool>> {
+        let mut map = MemoryMap::with_ram(0x20).unwrap();
+        for device in devices {
+            map.add(device).unwrap();
+        }
+        let io = Buffer::default();
+        let mut vcpu = Vcpu::new(
+            CpuType::Nand,
+            prog,
+            Profile::Call,
+            Ret::Zero,
+            Stack::Unbounded,
+            io,
+        );
+        vcpu.map = map;
+        vcpu
+    }
+
+    #[test]
+    fn memory_map() {
+        assert!(MemoryMap::with_ram(0xfe).is_err());
+        let mut map = MemoryMap::with_ram(0x20).unwrap();
+        assert!(map.add("rng@0x10").is_ok());
+        assert!(map.add("led@0x10").is_err()); // already mapped
+        assert!(map.add("led@0xfc:2").is_err()); // out of 0x00..0xfc
+        assert!(map.add("led@0x11:0").is_err());
+        assert!(map.add("seg7@0x18:1").is_err());
+        assert!(map.add("lamp@0x18").is_err());
+        assert!(map.add("cycle@0x18:x").is_err());
+        assert!(map.add("led@0x30").is_ok()); // unmapped RAM address
+        assert!(map.is_ram(0x00) && !map.is_ram(0x10) && !map.is_ram(0x20));
+    }
+
+    #[test]
+    fn unmapped() {
+        let mut vcpu = vcpu(vec![(0x00, 0xfe, 0xfe), (0x20, 0xfe, 0xfe)], &[]);
+        let fault = CpuFault::Unmapped;
+        assert_eq!(
+            vcpu.runner(),
+            Halt::Fault {
+                fault,
+                pc: 1,
+                step: 2
+            }
+        );
+    }
+
+    #[test]
+    fn cycle() {
+        // write: read only
+        let mut vcpu = vcpu(vec![(0x10, 0xfe, 0xfe)], &["cycle@0x10:2"]);
+        vcpu.runner();
+        assert!(!vcpu.data[0x10]);
+        let bits: Vec<bool> = (0..6)
+            .map(|steps| {
+                vcpu.stats.steps = steps;
+                vcpu.device_rd(0x10)
+            })
+            .collect();
+        assert_eq!(bits, [false, false, true, true, false, false]);
+        assert_eq!(vcpu.device_reads, 6);
+    }
+
+    #[test]
+    fn rng() {
+        let bits = |seed: &str| -> Vec<bool> {
+            let mut vcpu = vcpu(vec![(0x10, 0xfe, 0xfe)], &[&format!("rng@0x10:{seed}")]);
+            vcpu.runner();
+            assert!(!vcpu.data[0x10]);
+            (0..64)
+                .map(|steps| {
+                    vcpu.stats.steps = steps;
+                    vcpu.device_rd(0x10)
+                })
+                .collect()
+        };
+        let sequence = bits("7");
+        assert_eq!(sequence, bits("7"));
+        assert_ne!(sequence, bits("8"));
+        assert!(sequence.contains(&true) && sequence.contains(&false));
+    }
+
+    #[test]
+    fn led_and_segment() {
+        // led 0x10 = 1, 0x00 = !led 0x10, dp of the digit = 1, 0x01 = !dp
+        let prog = vec![
+            (0x10, 0xfe, 0xfe),
+            (0x00, 0x10, 0xff),
+            (0x1f, 0xfe, 0xfe),
+            (0x01, 0x1f, 0xff),
+        ];
+        let mut vcpu = vcpu(prog, &["led@0x10:4", "seg7@0x18"]);
+        vcpu.data[0x00] = true;
+        vcpu.data[0x01] = true;
+        assert_eq!(vcpu.runner(), Halt::End);
+        assert!(vcpu.data[0x10] && vcpu.data[0x1f]);
+        assert!(!vcpu.data[0x00] && !vcpu.data[0x01]);
+    }
+
+    #[test]
+    fn index_register() {
+        // pointer = 0b101, window = 1, 0x00 = !window, 0x01 = !pointer MSB
+        let prog = vec![
+            (0x10, 0xfe, 0xfe),
+            (0x10, 0xff, 0xff),
+            (0x10, 0xfe, 0xfe),
+            (0x11, 0xfe, 0xfe),
+            (0x00, 0x11, 0xff),
+            (0x01, 0x10, 0xff),
+        ];
+        let mut vcpu = vcpu(prog, &["index@0x10"]);
+        vcpu.data[0x00] = true;
+        assert_eq!(vcpu.runner(), Halt::End);
+        assert_eq!(vcpu.index, 0x05);
+        assert!(vcpu.data[0x05] && !vcpu.data[0x00] && vcpu.data[0x01]);
+        assert!(!vcpu.data[0x11]);
+    }
+
+    #[test]
+    fn index_window_unmapped() {
+        // the window selects no RAM bit: the index register, stdin
+        for pointer in [0x10, 0xfd] {
+            let mut vcpu = vcpu(vec![(0x00, 0x11, 0xff)], &["index@0x10"]);
+            vcpu.index = pointer;
+            let fault = CpuFault::Unmapped;
+            assert_eq!(
+                vcpu.runner(),
+                Halt::Fault {
+                    fault,
+                    pc: 0,
+                    step: 1
+                }
+            );
+        }
+    }
+
+    // target = 0b101, then the instruction
+    fn latched(instr: Instr) -> Vec<Instr> {
+        vec![
+            (0x10, 0xfe, 0xfe),
+            (0x10, 0xff, 0xff),
+            (0x10, 0xfe, 0xfe),
+            instr,
+        ]
+    }
+
+    #[test]
+    fn jump_latch() {
+        // jmp [0x10], 0x00 = 1 (jumped over), 0x01 = !target MSB
+        let mut prog = latched((0x11, 0xfe, 0xfe));
+        prog.extend([(0x00, 0xfe, 0xfe), (0x01, 0x10, 0xff)]);
+        let mut vcpu = vcpu(prog, &["jump@0x10"]);
+        assert_eq!(vcpu.runner(), Halt::End);
+        assert_eq!(vcpu.latch, 0x0005);
+        assert!(!vcpu.data[0x00] && vcpu.data[0x01]);
+        assert_eq!(vcpu.stats.jumps, 1);
+    }
+
+    #[test]
+    fn call_latch() {
+        // call [0x10], jmp 0x0006, ret
+        let mut prog = latched((0x12, 0xfe, 0xfe));
+        prog.extend([(0xff, 0x00, 0x06), (0xfc, 0x00, 0x00)]);
+        let mut call = vcpu(prog.clone(), &["jump@0x10"]);
+        assert_eq!(call.runner(), Halt::End);
+        assert_eq!((call.stats.calls, call.stats.rets), (1, 1));
+        // base profile: no call [latch]
+        let mut base = vcpu(prog, &["jump@0x10"]);
+        base.profile = Profile::Base;
+        let fault = CpuFault::Unmapped;
+        assert_eq!(
+            base.runner(),
+            Halt::Fault {
+                fault,
+                pc: 3,
+                step: 4
+            }
+        );
+    }
+}
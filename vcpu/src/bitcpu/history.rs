@@ -0,0 +1,244 @@
+// Undo log of the bit CPU for reverse execution (debugger, gdb stub)
+//
+// One entry per executed instruction: pc, overwritten RAM bit, call stack
+// change, I/O and the event counters of loop detection. Manual edits of the
+// debugger and the gdb stub (RAM bit, pc) have entries too, undone with the
+// instruction before them. The stdin bits are
+// logged and replayed when the instructions are executed again, also the
+// end of stdin, stdout bits are not written twice.
+
+use super::Vcpu;
+use crate::io::BitIo;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum Event {
+    None,
+    Skip,
+    Jump,
+    Call(Option<usize>), // overwritten return address (wrap, latch)
+    Ret(usize),          // popped return address
+    Edit,                // manual edit, not an instruction
+}
+
+#[derive(Clone, Copy)]
+pub(super) struct Undo {
+    pub(super) pc: usize,
+    pub(super) write: Option<(u8, bool)>, // RAM bit, old value
+    pub(super) index: u8,                 // index register before
+    pub(super) latch: u16,                // jump target before
+    pub(super) event: Event,
+    pub(super) stdin: u8, // bits read
+    pub(super) stdout: bool,
+    pub(super) device_reads: u64,   // timer and random reads before
+    pub(super) eof_at: Option<u64>, // end of stdin before
+}
+
+#[derive(Default)]
+pub(super) struct History {
+    undo: Vec<Undo>,
+    stdin: Vec<bool>,    // every stdin bit read
+    eof_at: Option<u64>, // stdin bits before the end of the input
+    stdout: u64,         // stdout bits written
+}
+
+impl<I: BitIo> Vcpu<I> {
+    // start recording, from the reset state
+    pub(crate) fn record(&mut self) {
+        self.history = Some(History::default());
+    }
+
+    pub(super) fn log_step(&mut self, undo: Undo) {
+        if let Some(history) = &mut self.history {
+            history.undo.push(undo);
+        }
+    }
+
+    // after stats.stdin += 1: the bit of an earlier execution, a read at
+    // the end of stdin finds the end again
+    pub(super) fn replay_bit(&mut self) -> Option<bool> {
+        let history = self.history.as_ref()?;
+        let bit = history.stdin.get(self.stats.stdin as usize - 1).copied()?;
+        if let Some(bits) = history.eof_at.filter(|&bits| self.stats.stdin > bits) {
+            self.eof_at.get_or_insert(bits);
+        }
+        Some(bit)
+    }
+
+    pub(super) fn log_bit(&mut self, bit: bool) {
+        if let Some(history) = &mut self.history {
+            history.stdin.push(bit);
+            history.eof_at = history.eof_at.or(self.eof_at);
+        }
+    }
+
+    // after stats.stdout += 1: false if the bit is already written
+    pub(super) fn new_output(&mut self) -> bool {
+        match &mut self.history {
+            Some(history) if self.stats.stdout <= history.stdout => false,
+            Some(history) => {
+                history.stdout = self.stats.stdout;
+                true
+            }
+            None => true,
+        }
+    }
+
+    // stdin bits without reading stdin: replay and queue
+    pub(crate) fn stdin_available(&self) -> usize {
+        let replay = match &self.history {
+            Some(history) => history.stdin.len() - self.stats.stdin as usize,
+            None => 0,
+        };
+        replay + self.stdin_queue.len()
+    }
+
+    // manual edit of the debugger or gdb stub, before the change
+    fn log_edit(&mut self, write: Option<(u8, bool)>) {
+        let undo = Undo {
+            pc: self.pc,
+            write,
+            index: self.index,
+            latch: self.latch,
+            event: Event::Edit,
+            stdin: 0,
+            stdout: false,
+            device_reads: self.device_reads,
+            eof_at: self.eof_at,
+        };
+        self.log_step(undo);
+    }
+
+    pub(crate) fn set_bit(&mut self, bit: u8, value: bool) {
+        self.log_edit(Some((bit, self.data[bit as usize])));
+        self.data[bit as usize] = value;
+    }
+
+    pub(crate) fn set_pc(&mut self, pc: usize) {
+        self.log_edit(None);
+        self.pc = pc;
+    }
+
+    // undo the last instruction and the manual edits after it, false: no
+    // more instructions
+    pub(crate) fn unstep(&mut self) -> bool {
+        loop {
+            let Some(undo) = self.history.as_mut().and_then(|h| h.undo.pop()) else {
+                return false;
+            };
+            self.undo(undo);
+            self.fault = None;
+            if undo.event != Event::Edit {
+                return true;
+            }
+        }
+    }
+
+    fn undo(&mut self, undo: Undo) {
+        if let Some((bit, old)) = undo.write {
+            self.data[bit as usize] = old;
+        }
+        self.index = undo.index;
+        self.latch = undo.latch;
+        self.device_reads = undo.device_reads;
+        self.eof_at = undo.eof_at;
+        self.pc = undo.pc;
+        match undo.event {
+            Event::Edit => return,
+            Event::None => (),
+            Event::Skip => self.stats.skips -= 1,
+            Event::Jump => self.stats.jumps -= 1,
+            Event::Call(lost) => {
+                self.stats.calls -= 1;
+                self.pc_save.pop();
+                if let Some(addr) = lost {
+                    self.pc_save.insert(0, addr);
+                }
+            }
+            Event::Ret(addr) => {
+                self.stats.rets -= 1;
+                self.pc_save.push(addr);
+            }
+        }
+        if undo.stdout {
+            self.stats.stdout -= 1;
+        }
+        self.stats.stdin -= undo.stdin as u64;
+        self.stats.steps -= 1;
+    }
+
+    // (step number, pc) of the last write of the RAM bit by an instruction
+    pub(crate) fn last_write(&self, bit: u8) -> Option<(u64, usize)> {
+        let undo = &self.history.as_ref()?.undo;
+        let executed = |u: &&Undo| u.event != Event::Edit;
+        let i = undo
+            .iter()
+            .rposition(|u| executed(&u) && u.write.is_some_and(|(b, _)| b == bit))?;
+        let step = undo[..=i].iter().filter(executed).count();
+        Some((step as u64, undo[i].pc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcpu::{CpuType, Eof, Instr, Profile, Ret, Stack};
+    use crate::io::Buffer;
+    use crate::Cpu;
+
+    fn vcpu(prog: Vec<Instr>, input: &[bool]) -> Vcpu<Buffer<bool>> {
+        let io = Buffer::new(input.to_vec());
+        let mut vcpu = Vcpu::new(
+            CpuType::Nand,
+            prog,
+            Profile::Base,
+            Ret::Zero,
+            Stack::Unbounded,
+            io,
+        );
+        vcpu.record();
+        vcpu
+    }
+
+    #[test]
+    fn unstep_manual_edits() {
+        // 0x01 = nand(0, 0)
+        let mut vcpu = vcpu(vec![(0x01, 0xfe, 0xfe); 2], &[]);
+        assert!(vcpu.step());
+        vcpu.set_bit(0x00, true);
+        vcpu.set_bit(0x01, false);
+        vcpu.set_pc(0);
+        assert_eq!(vcpu.last_write(0x01), Some((1, 0)));
+        assert!(vcpu.unstep());
+        assert_eq!((vcpu.pc, vcpu.stats.steps), (0, 0));
+        assert!(!vcpu.data[0x00] && !vcpu.data[0x01]);
+        assert!(!vcpu.unstep());
+    }
+
+    #[test]
+    fn unstep_end_of_stdin() {
+        // 0x00 = nand(stdin, stdin), the second bit is at the end of stdin
+        let mut vcpu = vcpu(vec![(0x00, 0xfd, 0xfd); 2], &[true]);
+        vcpu.eof = Eof::Zero;
+        assert!(vcpu.step() && vcpu.step());
+        assert_eq!((vcpu.eof_at, vcpu.stats.stdin), (Some(1), 4));
+        assert!(vcpu.unstep());
+        assert_eq!((vcpu.eof_at, vcpu.stats.stdin), (Some(1), 2));
+        assert!(vcpu.unstep());
+        assert_eq!((vcpu.eof_at, vcpu.stats.stdin), (None, 0));
+        assert!(vcpu.step()); // replayed
+        assert_eq!((vcpu.eof_at, vcpu.stats.stdin), (Some(1), 2));
+    }
+
+    #[test]
+    fn unstep_device_reads() {
+        // 0x00 = nand(rng, cycle)
+        let mut vcpu = vcpu(vec![(0x00, 0x10, 0x11)], &[]);
+        vcpu.map.add("rng@0x10").unwrap();
+        vcpu.map.add("cycle@0x11").unwrap();
+        assert!(vcpu.step());
+        assert_eq!(vcpu.device_reads, 2);
+        assert!(vcpu.unstep());
+        assert_eq!(vcpu.device_reads, 0);
+        assert!(!vcpu.unstep());
+    }
+}
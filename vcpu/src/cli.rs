@@ -0,0 +1,291 @@
+// Command line front ends of the emulators
+//
+// bitcpu <file.nand> [trace|debug] [--profile base|call] [--ret zero|msb]
+//        [--stack unbounded|latch|N|N:wrap] [--max-steps N] [--stats]
+//        [--detect-loops] [--symbols file.sym] [--input FILE] [--output FILE]
+//        [--eof fault|0|1|status=ADDR] [--io text|msb|lsb]
+//        [--ram N] [--device KIND@ADDR[:ARG]]...
+//        [--gdb PORT|stdio] [--vcd file.vcd] [--json file.jsonl|-] [--json-mem]
+//        [--prof] [--flamegraph file.folded] [--coverage file.info]
+// subleq <file.subleq> [--max-steps N] [--stats] [--input FILE] [--output FILE]
+// Profile, ret and stack: command line, else program header, else the
+// default (profile of the binary, ret zero, unbounded stack).
+//
+// Exit status: 0 end of program, 2 --max-steps limit hit,
+//              3 non-terminating loop (--detect-loops),
+//              faults: 4 ret with an empty call stack, 5 jump target out
+//              of range, 6 ROM overflow, 7 invalid output character,
+//              8 end of stdin, 9 call with a full call stack,
+//              10 access to an unmapped address (--ram)
+// debug, --gdb: the exit status of the state at the end of the session
+// --stats: run statistics on stderr at exit
+// --prof: profile report on stderr at exit
+// --coverage: lcov report, merged into an existing file
+// --input, --output: files instead of the program stdin/stdout
+// --eof: stdin bit read at the end of the input, status=ADDR: the RAM bit
+//        ADDR reads 1 after the first read at the end (default: fault),
+//        ADDR is neither unmapped nor a device
+// --io: stdin/stdout bits as '0'/'1' text (default) or bytes, msb or lsb
+//       first
+// --ram, --device: memory map, see bitcpu/devices.rs
+
+use crate::bitcpu::{self, Eof, MemoryMap, Profile, Ret, Stack};
+use crate::coverage::Coverage;
+use crate::debug::Debugger;
+use crate::gdb::Stub;
+use crate::io::{BitFormat, Stream};
+use crate::profile::{Flamegraph, Profiler};
+use crate::symbols::{self, Symbols};
+use crate::trace::JsonLines;
+use crate::vcd::Vcd;
+use crate::{subleq, CpuFault, Halt, Stats};
+use std::fs::File;
+use std::io::Read;
+
+fn read_program(fname: &str) -> String {
+    let mut file = File::open(fname).expect("program file not found");
+    let mut src = String::new();
+    file.read_to_string(&mut src).expect("failed to read");
+    src
+}
+
+fn option_error(usage: &str, option: &str) -> ! {
+    eprintln!("invalid option: {option}");
+    eprintln!("{usage}");
+    std::process::exit(-1);
+}
+
+// program stdin/stdout, or files
+fn open_io(input: Option<String>, output: Option<String>) -> Stream {
+    Stream::open(input.as_deref(), output.as_deref()).unwrap_or_else(|err| {
+        eprintln!("--input/--output: {err}");
+        std::process::exit(-1);
+    })
+}
+
+pub const EXIT_MAX_STEPS: i32 = 2;
+pub const EXIT_LOOP: i32 = 3;
+
+pub fn exit_code(fault: CpuFault) -> i32 {
+    match fault {
+        CpuFault::StackUnderflow => 4,
+        CpuFault::PcOutOfRange => 5,
+        CpuFault::RomOverflow => 6,
+        CpuFault::InvalidOutput => 7,
+        CpuFault::InputEof => 8,
+        CpuFault::StackOverflow => 9,
+        CpuFault::Unmapped => 10,
+    }
+}
+
+fn parse_max_steps(usage: &str, value: Option<String>) -> Option<u64> {
+    let value = value.unwrap_or_default();
+    Some(
+        value
+            .parse()
+            .unwrap_or_else(|_| option_error(usage, &value)),
+    )
+}
+
+fn finish(halt: Halt, stats: &Stats, show_stats: bool, unit: &str) {
+    if show_stats {
+        eprintln!("{}", stats.report(unit));
+    }
+    match halt {
+        Halt::End => (),
+        Halt::MaxSteps => {
+            eprintln!("step limit reached: {} instructions", stats.steps);
+            std::process::exit(EXIT_MAX_STEPS);
+        }
+        Halt::Loop { pc, first, last } => {
+            eprintln!("non-terminating loop at pc={pc:04x} (loop: {first:04x}..{last:04x})");
+            std::process::exit(EXIT_LOOP);
+        }
+        Halt::Fault { fault, pc, step } => {
+            eprintln!("fault: {} at pc={pc:04x}, step {step}", fault.message());
+            std::process::exit(exit_code(fault));
+        }
+    }
+}
+
+pub fn bitcpu(name: &str, default_profile: Profile) {
+    let usage = format!(
+        "usage: {name} <file.nand> [trace|debug] [--profile base|call] [--ret zero|msb]\n\
+         \x20      [--stack unbounded|latch|N|N:wrap] [--max-steps N] [--stats]\n\
+         \x20      [--detect-loops] [--symbols file.sym] [--input FILE] [--output FILE]\n\
+         \x20      [--eof fault|0|1|status=ADDR] [--io text|msb|lsb]\n\
+         \x20      [--ram N] [--device cycle|rng|led|seg7|index|jump@ADDR[:ARG]]...\n\
+         \x20      [--gdb PORT|stdio] [--vcd file.vcd] [--json file.jsonl|-] [--json-mem]\n\
+         \x20      [--prof] [--flamegraph file.folded] [--coverage file.info]"
+    );
+    let mut fname = None;
+    let mut trace = false;
+    let mut profile = None;
+    let mut ret = None;
+    let mut stack = None;
+    let mut max_steps = None;
+    let mut show_stats = false;
+    let mut detect_loop = false;
+    let mut debug = false;
+    let mut symbols = Symbols::default();
+    let mut gdb = None;
+    let mut vcd = None;
+    let mut json = None;
+    let mut json_mem = false;
+    let mut prof = false;
+    let mut flamegraph = None;
+    let mut coverage = None;
+    let mut input = None;
+    let mut output = None;
+    let mut eof = Eof::Fault;
+    let mut format = BitFormat::Text;
+    let mut ram = 0xfd;
+    let mut devices = vec![];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "trace" => trace = true,
+            "debug" => debug = true,
+            "--gdb" => gdb = Some(args.next().unwrap_or_default()),
+            "--symbols" => symbols = Symbols::load(&args.next().unwrap_or_default()),
+            "--detect-loops" => detect_loop = true,
+            "--vcd" => vcd = Some(args.next().unwrap_or_default()),
+            "--json" => json = Some(args.next().unwrap_or_default()),
+            "--json-mem" => json_mem = true,
+            "--prof" => prof = true,
+            "--flamegraph" => flamegraph = Some(args.next().unwrap_or_default()),
+            "--coverage" => coverage = Some(args.next().unwrap_or_default()),
+            "--input" => input = Some(args.next().unwrap_or_default()),
+            "--output" => output = Some(args.next().unwrap_or_default()),
+            "--eof" => {
+                let value = args.next().unwrap_or_default();
+                eof = Eof::parse(&value).unwrap_or_else(|| option_error(&usage, &value));
+            }
+            "--ram" => {
+                let value = args.next().unwrap_or_default();
+                ram = symbols::parse_number(&value).unwrap_or_else(|| option_error(&usage, &value));
+            }
+            "--device" => devices.push(args.next().unwrap_or_default()),
+            "--io" => {
+                let value = args.next().unwrap_or_default();
+                format = BitFormat::parse(&value).unwrap_or_else(|| option_error(&usage, &value));
+            }
+            "--profile" => {
+                let value = args.next().unwrap_or_default();
+                profile =
+                    Some(Profile::parse(&value).unwrap_or_else(|| option_error(&usage, &value)));
+            }
+            "--ret" => {
+                let value = args.next().unwrap_or_default();
+                ret = Some(Ret::parse(&value).unwrap_or_else(|| option_error(&usage, &value)));
+            }
+            "--stack" => {
+                let value = args.next().unwrap_or_default();
+                stack = Some(Stack::parse(&value).unwrap_or_else(|| option_error(&usage, &value)));
+            }
+            "--max-steps" => max_steps = parse_max_steps(&usage, args.next()),
+            "--stats" => show_stats = true,
+            _ if fname.is_none() && !arg.starts_with("--") => fname = Some(arg),
+            _ => option_error(&usage, &arg),
+        }
+    }
+    let Some(fname) = fname else {
+        eprintln!("{usage}");
+        return;
+    };
+    let src = read_program(&fname);
+    let (cputype, header, prog) = bitcpu::compiler(&src).unwrap_or_else(|err| {
+        eprintln!("{fname}: {err}");
+        std::process::exit(-1);
+    });
+    let profile = profile.or(header.profile).unwrap_or(default_profile);
+    let ret = ret.or(header.ret).unwrap_or(Ret::Zero);
+    let stack = stack.or(header.stack).unwrap_or(Stack::Unbounded);
+    let stdin_commands = debug && input.is_none();
+    let mut io = open_io(input, output);
+    io.format = format;
+    let mut vcpu = bitcpu::Vcpu::new(cputype, prog, profile, ret, stack, io);
+    vcpu.trace = trace;
+    vcpu.max_steps = max_steps;
+    vcpu.detect_loop = detect_loop;
+    vcpu.eof = eof;
+    vcpu.map = MemoryMap::with_ram(ram)
+        .and_then(|mut map| {
+            for device in &devices {
+                map.add(device)?;
+            }
+            match eof {
+                Eof::Status(addr) if !map.is_ram(addr) => Err(format!(
+                    "--eof status=0x{addr:02x}: not a RAM bit (--ram, --device)"
+                )),
+                _ => Ok(map),
+            }
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("{err}");
+            std::process::exit(-1);
+        });
+    if let Some(fname) = vcd {
+        let vcd = Vcd::create(&fname, &vcpu, &symbols);
+        vcpu.tracers.push(Box::new(vcd));
+    }
+    if let Some(fname) = json {
+        vcpu.tracers
+            .push(Box::new(JsonLines::create(&fname, json_mem)));
+    }
+    if prof {
+        vcpu.tracers.push(Box::new(Profiler::new(&symbols)));
+    }
+    if let Some(fname) = flamegraph {
+        vcpu.tracers
+            .push(Box::new(Flamegraph::new(&fname, &symbols)));
+    }
+    if let Some(info) = coverage {
+        let coverage = Coverage::new(&info, &fname, &src, &symbols);
+        vcpu.tracers.push(Box::new(coverage));
+    }
+    let halt = if let Some(target) = gdb {
+        Stub::new(&mut vcpu, &target).serve();
+        vcpu.halt()
+    } else if debug {
+        let mut debugger = Debugger::new(&mut vcpu, &symbols);
+        debugger.queued_input = stdin_commands;
+        debugger.repl();
+        vcpu.halt()
+    } else {
+        vcpu.runner()
+    };
+    vcpu.finish_trace();
+    finish(halt, &vcpu.stats, show_stats, "bits");
+}
+
+pub fn subleq() {
+    let usage =
+        "usage: subleq <file.subleq> [--max-steps N] [--stats] [--input FILE] [--output FILE]";
+    let mut fname = None;
+    let mut max_steps = None;
+    let mut show_stats = false;
+    let mut input = None;
+    let mut output = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-steps" => max_steps = parse_max_steps(usage, args.next()),
+            "--stats" => show_stats = true,
+            "--input" => input = Some(args.next().unwrap_or_default()),
+            "--output" => output = Some(args.next().unwrap_or_default()),
+            _ if fname.is_none() && !arg.starts_with("--") => fname = Some(arg),
+            _ => option_error(usage, &arg),
+        }
+    }
+    let Some(fname) = fname else {
+        eprintln!("{usage}");
+        return;
+    };
+    let (cputype, prog, rom) = subleq::compiler(&read_program(&fname));
+    let io = open_io(input, output);
+    let mut vcpu = subleq::Vcpu::new(cputype, prog, rom, io);
+    vcpu.max_steps = max_steps;
+    let halt = vcpu.runner();
+    finish(halt, &vcpu.stats, show_stats, "chars");
+}
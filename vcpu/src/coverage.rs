@@ -0,0 +1,259 @@
+// Code coverage of the bit CPU in lcov format (.info, genhtml)
+//
+// DA: executed instructions per source line, BRDA: skip instructions,
+// branch 0: next instruction executed, branch 1: skipped. Source lines
+// from the symbol file (line entries of the assembler), else the lines
+// of the program file. An existing .info file is merged: a set of runs
+// accumulates into one report.
+
+use crate::bitcpu::{self, Vcpu};
+use crate::io::BitIo;
+use crate::symbols::Symbols;
+use crate::trace::{Step, Tracer};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+#[derive(Default)]
+struct Record {
+    functions: BTreeMap<String, (usize, u64)>, // label: (line, calls)
+    lines: BTreeMap<usize, u64>,
+    branches: BTreeMap<(usize, usize, usize), Option<u64>>, // (line, block, branch)
+}
+
+fn add_branch(
+    branches: &mut BTreeMap<(usize, usize, usize), Option<u64>>,
+    key: (usize, usize, usize),
+    taken: Option<u64>,
+) {
+    let entry = branches.entry(key).or_insert(None);
+    *entry = match (*entry, taken) {
+        (Some(a), Some(b)) => Some(a + b),
+        (a, b) => a.or(b),
+    };
+}
+
+// records of an existing .info file
+fn parse_info(src: &str) -> BTreeMap<String, Record> {
+    let mut records: BTreeMap<String, Record> = BTreeMap::new();
+    let mut file = String::new();
+    for line in src.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let fields: Vec<&str> = value.split(',').collect();
+        let record = records.entry(file.clone()).or_default();
+        match (key, &fields[..]) {
+            ("SF", _) => file = value.to_string(),
+            ("FN", [linenum, name]) => {
+                let linenum = linenum.parse().unwrap_or(0);
+                record
+                    .functions
+                    .entry(name.to_string())
+                    .or_insert((linenum, 0));
+            }
+            ("FNDA", [count, name]) => {
+                let count: u64 = count.parse().unwrap_or(0);
+                record.functions.entry(name.to_string()).or_default().1 += count;
+            }
+            ("DA", [linenum, count]) => {
+                if let (Ok(linenum), Ok(count)) = (linenum.parse(), count.parse::<u64>()) {
+                    *record.lines.entry(linenum).or_default() += count;
+                }
+            }
+            ("BRDA", [linenum, block, branch, taken]) => {
+                if let (Ok(linenum), Ok(block), Ok(branch)) =
+                    (linenum.parse(), block.parse(), branch.parse())
+                {
+                    let key = (linenum, block, branch);
+                    add_branch(&mut record.branches, key, taken.parse().ok());
+                }
+            }
+            _ => (),
+        }
+    }
+    records.remove("");
+    records
+}
+
+pub struct Coverage {
+    fname: String,
+    program: String, // program file: source without symbol file
+    program_lines: Vec<usize>,
+    symbols: Symbols,
+    hits: Vec<u64>,
+    skips: Vec<[u64; 2]>, // next executed, skipped
+}
+
+impl Coverage {
+    pub fn new(fname: &str, program: &str, src: &str, symbols: &Symbols) -> Self {
+        Coverage {
+            fname: fname.to_string(),
+            program: program.to_string(),
+            program_lines: bitcpu::source_lines(src),
+            symbols: symbols.clone(),
+            hits: vec![],
+            skips: vec![],
+        }
+    }
+
+    fn source(&self, pc: usize) -> (String, usize) {
+        match self.symbols.source(pc) {
+            Some((file, linenum)) => (file.to_string(), linenum),
+            None => (
+                self.program.clone(),
+                self.program_lines.get(pc).copied().unwrap_or(0),
+            ),
+        }
+    }
+
+    fn records<I: BitIo>(&self, vcpu: &Vcpu<I>) -> BTreeMap<String, Record> {
+        let mut records: BTreeMap<String, Record> = BTreeMap::new();
+        for pc in 0..vcpu.prog.len() {
+            let (file, linenum) = self.source(pc);
+            let record = records.entry(file).or_default();
+            let hits = self.hits.get(pc).copied().unwrap_or(0);
+            *record.lines.entry(linenum).or_default() += hits;
+            if let Some(label) = self.symbols.label_at(pc) {
+                record.functions.insert(label.to_string(), (linenum, hits));
+            }
+            if vcpu.prog[pc].0 == 0xfe {
+                let [next, skipped] = self.skips.get(pc).copied().unwrap_or_default();
+                let executed = |count| (hits > 0).then_some(count);
+                add_branch(&mut record.branches, (linenum, pc, 0), executed(next));
+                add_branch(&mut record.branches, (linenum, pc, 1), executed(skipped));
+            }
+        }
+        records
+    }
+}
+
+impl<I: BitIo> Tracer<I> for Coverage {
+    fn step(&mut self, _vcpu: &Vcpu<I>, step: &Step) {
+        if self.hits.len() <= step.pc {
+            self.hits.resize(step.pc + 1, 0);
+            self.skips.resize(step.pc + 1, [0, 0]);
+        }
+        self.hits[step.pc] += 1;
+        if step.instr.0 == 0xfe {
+            self.skips[step.pc][step.skip as usize] += 1;
+        }
+    }
+
+    fn finish(&mut self, vcpu: &Vcpu<I>) {
+        let mut records = match fs::read_to_string(&self.fname) {
+            Ok(src) => parse_info(&src),
+            Err(_) => BTreeMap::new(),
+        };
+        for (file, run) in self.records(vcpu) {
+            let record = records.entry(file).or_default();
+            for (name, (linenum, count)) in run.functions {
+                record.functions.entry(name).or_insert((linenum, 0)).1 += count;
+            }
+            for (linenum, count) in run.lines {
+                *record.lines.entry(linenum).or_default() += count;
+            }
+            for (key, taken) in run.branches {
+                add_branch(&mut record.branches, key, taken);
+            }
+        }
+        let file = File::create(&self.fname).unwrap_or_else(|err| {
+            eprintln!("{}: {err}", self.fname);
+            std::process::exit(-1);
+        });
+        let mut out = BufWriter::new(file);
+        for (file, record) in records {
+            writeln!(out, "TN:").unwrap();
+            writeln!(out, "SF:{file}").unwrap();
+            for (name, (linenum, _)) in &record.functions {
+                writeln!(out, "FN:{linenum},{name}").unwrap();
+            }
+            for (name, (_, count)) in &record.functions {
+                writeln!(out, "FNDA:{count},{name}").unwrap();
+            }
+            let hit = record.functions.values().filter(|f| f.1 > 0).count();
+            writeln!(out, "FNF:{}", record.functions.len()).unwrap();
+            writeln!(out, "FNH:{hit}").unwrap();
+            for ((linenum, block, branch), taken) in &record.branches {
+                let taken = taken.map_or("-".to_string(), |t| t.to_string());
+                writeln!(out, "BRDA:{linenum},{block},{branch},{taken}").unwrap();
+            }
+            let hit = record
+                .branches
+                .values()
+                .filter(|t| t.unwrap_or(0) > 0)
+                .count();
+            writeln!(out, "BRF:{}", record.branches.len()).unwrap();
+            writeln!(out, "BRH:{hit}").unwrap();
+            for (linenum, count) in &record.lines {
+                writeln!(out, "DA:{linenum},{count}").unwrap();
+            }
+            let hit = record.lines.values().filter(|&&count| count > 0).count();
+            writeln!(out, "LF:{}", record.lines.len()).unwrap();
+            writeln!(out, "LH:{hit}").unwrap();
+            writeln!(out, "end_of_record").unwrap();
+        }
+        out.flush().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcpu::{Profile, Ret, Stack};
+    use crate::io::Buffer;
+
+    // skip_nand(stdin, 1), 0x10 = 1, 0x11 = 1
+    const SRC: &str = "NAND_CPU\n0xfefdff\n0x10fefe  # skipped by stdin 0\n\n0x11fefe\n";
+
+    // one run with the stdin bit, merged into the .info file
+    fn run(fname: &str, input: bool) {
+        let (cputype, _, prog) = bitcpu::compiler(SRC).unwrap();
+        let io = Buffer::new([input]);
+        let mut vcpu = Vcpu::new(
+            cputype,
+            prog,
+            Profile::Call,
+            Ret::Zero,
+            Stack::Unbounded,
+            io,
+        );
+        let symbols = Symbols::parse("label start 0x0000\n");
+        let coverage = Coverage::new(fname, "prog.nand", SRC, &symbols);
+        vcpu.tracers.push(Box::new(coverage));
+        vcpu.runner();
+        vcpu.finish_trace();
+    }
+
+    #[test]
+    fn merge_runs() {
+        let fname = std::env::temp_dir().join(format!("vcpu-test-{}.info", std::process::id()));
+        let fname = fname.to_str().unwrap();
+        let _ = fs::remove_file(fname);
+        run(fname, true);
+        let first = fs::read_to_string(fname).unwrap();
+        assert!(first.contains("BRDA:2,0,0,1\nBRDA:2,0,1,0\n"));
+        assert!(first.contains("DA:2,1\nDA:3,1\nDA:5,1\n"));
+        run(fname, false);
+        let merged = fs::read_to_string(fname).unwrap();
+        fs::remove_file(fname).unwrap();
+        assert_eq!(
+            merged,
+            "TN:\nSF:prog.nand\nFN:2,start\nFNDA:2,start\nFNF:1\nFNH:1\n\
+             BRDA:2,0,0,1\nBRDA:2,0,1,1\nBRF:2\nBRH:2\n\
+             DA:2,2\nDA:3,1\nDA:5,2\nLF:3\nLH:3\nend_of_record\n"
+        );
+    }
+
+    #[test]
+    fn parse_and_add() {
+        let src = "TN:\nSF:a.asm\nFN:3,main\nFNDA:1,main\nFNDA:2,main\n\
+                   BRDA:4,0,0,-\nBRDA:4,0,0,3\nBRDA:4,0,1,-\nDA:3,1\nDA:3,2\nend_of_record\n";
+        let records = parse_info(src);
+        let record = &records["a.asm"];
+        assert_eq!(record.functions["main"], (3, 3));
+        assert_eq!(record.lines[&3], 3);
+        assert_eq!(record.branches[&(4, 0, 0)], Some(3));
+        assert_eq!(record.branches[&(4, 0, 1)], None);
+    }
+}
@@ -0,0 +1,453 @@
+// Interactive debugger of the bit CPU: bitcpu-base/bitcpu-call <file> debug
+//
+// Commands read from stdin, messages on stderr, the program output stays
+// on stdout. Program stdin: queued bits first ("input"), then the --input
+// file. Without --input the commands are on stdin, the program stops before
+// an instruction that needs more than the queued bits.
+// Every instruction is recorded for reverse execution, the stdin bits are
+// replayed when an instruction is executed again. A CPU fault stops before
+// the faulting instruction, e.g. end of stdin: queue bits and continue.
+
+use crate::bitcpu::Vcpu;
+use crate::io::{BitIo, Stream};
+use crate::symbols::Symbols;
+use crate::{Cpu, CpuFault};
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+const HELP: &str = "\
+s, step [N]          execute N (1) instructions
+n, next              step, a call is executed until its return
+f, finish            run until the current subroutine returns
+c, continue          run until a breakpoint, a watchpoint or the end
+rs, reverse-step [N] undo N (1) instructions
+rc, reverse-continue undo until a breakpoint, a watchpoint or the start
+last BIT             when was the RAM bit last written?
+b, break [PC|LABEL]  set a breakpoint, without argument: list breakpoints
+d, delete PC|LABEL   delete a breakpoint
+w, watch [BIT]       stop after a write of the RAM bit (address or equ name)
+u, unwatch BIT       delete a watchpoint
+x, mem [FROM [TO]]   dump RAM bits (default 0x00..0x7f)
+set BIT 0|1          set a RAM bit
+bt, stack            call stack (return addresses)
+i, input BITS        queue stdin bits, e.g. input 0110
+p, pc                current instruction
+q, quit              exit
+empty line: repeat the last command";
+
+enum Stop {
+    Start,
+    End,
+    Done,
+    Breakpoint,
+    Watchpoint(u8, bool, bool), // bit, old value, new value
+    Fault(CpuFault),
+    Input, // waits for queued stdin bits
+}
+
+pub struct Debugger<'a, I: BitIo = Stream> {
+    vcpu: &'a mut Vcpu<I>,
+    symbols: &'a Symbols,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<u8>,
+    pub queued_input: bool, // program stdin: queued bits only
+}
+
+impl<'a, I: BitIo> Debugger<'a, I> {
+    pub fn new(vcpu: &'a mut Vcpu<I>, symbols: &'a Symbols) -> Self {
+        vcpu.record();
+        Debugger {
+            vcpu,
+            symbols,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            queued_input: false,
+        }
+    }
+
+    fn location(&self, pc: usize) -> String {
+        match self.symbols.location(pc) {
+            Some(label) => format!("{pc:04x} <{label}>"),
+            None => format!("{pc:04x}"),
+        }
+    }
+
+    fn bit_name(&self, bit: u8) -> String {
+        match self.symbols.bit_name(bit) {
+            Some(name) => format!("0x{bit:02x} ({name})"),
+            None => format!("0x{bit:02x}"),
+        }
+    }
+
+    fn show_pc(&self) {
+        let pc = self.vcpu.pc;
+        if pc >= self.vcpu.prog.len() {
+            eprintln!("{}: end of program", self.location(pc));
+        } else {
+            eprintln!("{}: {}", self.location(pc), self.vcpu.disasm(pc));
+        }
+    }
+
+    // execute one or more instructions until done() or a stop event
+    fn run_until(&mut self, done: impl Fn(&Vcpu<I>) -> bool) -> Stop {
+        loop {
+            let pc = self.vcpu.pc;
+            if pc >= self.vcpu.prog.len() {
+                return Stop::End;
+            }
+            if self.queued_input && self.vcpu.stdin_available() < self.vcpu.stdin_reads(pc) {
+                return Stop::Input;
+            }
+            let dst = self.vcpu.effective(self.vcpu.prog[pc].0);
+            let old = self.vcpu.data[dst as usize];
+            if !self.vcpu.step() {
+                if let Some(fault) = self.vcpu.fault {
+                    return Stop::Fault(fault);
+                }
+            }
+            if self.watchpoints.contains(&dst) {
+                return Stop::Watchpoint(dst, old, self.vcpu.data[dst as usize]);
+            }
+            if done(self.vcpu) {
+                return Stop::Done;
+            }
+            if self.breakpoints.contains(&self.vcpu.pc) {
+                return Stop::Breakpoint;
+            }
+        }
+    }
+
+    // undo one or more instructions until done() or a stop event
+    fn run_back(&mut self, done: impl Fn(&Vcpu<I>) -> bool) -> Stop {
+        loop {
+            let before = self.vcpu.data;
+            if !self.vcpu.unstep() {
+                return Stop::Start;
+            }
+            let dst = self.vcpu.effective(self.vcpu.prog[self.vcpu.pc].0);
+            if self.watchpoints.contains(&dst) {
+                let bit = dst as usize;
+                return Stop::Watchpoint(dst, self.vcpu.data[bit], before[bit]);
+            }
+            if done(self.vcpu) {
+                return Stop::Done;
+            }
+            if self.breakpoints.contains(&self.vcpu.pc) {
+                return Stop::Breakpoint;
+            }
+        }
+    }
+
+    fn report(&self, stop: Stop) {
+        match stop {
+            Stop::Start => eprintln!("start of the recorded history"),
+            Stop::End => eprintln!(
+                "program finished after {} instructions",
+                self.vcpu.stats.steps
+            ),
+            Stop::Breakpoint => eprint!("breakpoint, "),
+            Stop::Watchpoint(bit, old, new) => eprintln!(
+                "watchpoint {}: {} -> {}",
+                self.bit_name(bit),
+                old as u8,
+                new as u8
+            ),
+            Stop::Fault(fault) => eprintln!("fault: {}", fault.message()),
+            Stop::Input => eprintln!("program waits for stdin: input BITS"),
+            Stop::Done => (),
+        }
+        io::stdout().flush().unwrap();
+        self.show_pc();
+    }
+
+    fn dump(&self, from: u8, to: u8) {
+        let mut line = String::new();
+        for addr in from..=to {
+            if addr == from || addr % 32 == 0 {
+                if !line.is_empty() {
+                    eprintln!("{line}");
+                }
+                line = format!("0x{addr:02x}:");
+            }
+            if addr % 4 == 0 {
+                line.push(' ');
+            }
+            if addr % 8 == 0 {
+                line.push(' ');
+            }
+            line.push((0x30 + self.vcpu.data[addr as usize] as u8) as char);
+        }
+        eprintln!("{line}");
+    }
+
+    // false: quit
+    fn command(&mut self, line: &str) -> bool {
+        let token: Vec<&str> = line.split_whitespace().collect();
+        let Some(&cmd) = token.first() else {
+            return true;
+        };
+        let arg = token.get(1).copied();
+        let depth = self.vcpu.pc_save.len();
+        match cmd {
+            "s" | "step" => {
+                let count = arg.and_then(|n| n.parse().ok()).unwrap_or(1);
+                let mut stop = Stop::Done;
+                for _ in 0..count {
+                    stop = self.run_until(|_| true);
+                    if !matches!(stop, Stop::Done) {
+                        break;
+                    }
+                }
+                self.report(stop);
+            }
+            "n" | "next" => {
+                let pc = self.vcpu.pc;
+                let call = pc < self.vcpu.prog.len() && self.vcpu.is_call(pc);
+                let stop = if call {
+                    self.run_until(|vcpu| vcpu.pc_save.len() <= depth)
+                } else {
+                    self.run_until(|_| true)
+                };
+                self.report(stop);
+            }
+            "f" | "finish" => {
+                if depth == 0 {
+                    eprintln!("not in a subroutine");
+                } else {
+                    let stop = self.run_until(|vcpu| vcpu.pc_save.len() < depth);
+                    self.report(stop);
+                }
+            }
+            "rs" | "reverse-step" => {
+                let count = arg.and_then(|n| n.parse().ok()).unwrap_or(1);
+                let mut stop = Stop::Done;
+                for _ in 0..count {
+                    stop = self.run_back(|_| true);
+                    if !matches!(stop, Stop::Done) {
+                        break;
+                    }
+                }
+                self.report(stop);
+            }
+            "rc" | "reverse-continue" => {
+                let stop = self.run_back(|_| false);
+                self.report(stop);
+            }
+            "last" => match arg.and_then(|value| self.symbols.bit_of(value)) {
+                Some(bit) => match self.vcpu.last_write(bit) {
+                    Some((step, pc)) => eprintln!(
+                        "{} last written at step {step} by {}: {}",
+                        self.bit_name(bit),
+                        self.location(pc),
+                        self.vcpu.disasm(pc)
+                    ),
+                    None => eprintln!("{} not written", self.bit_name(bit)),
+                },
+                None => eprintln!("usage: last BIT"),
+            },
+            "c" | "continue" => {
+                let stop = self.run_until(|_| false);
+                self.report(stop);
+            }
+            "b" | "break" => match arg {
+                None => {
+                    for &pc in &self.breakpoints {
+                        eprintln!("breakpoint {}", self.location(pc));
+                    }
+                }
+                Some(value) => match self.symbols.pc_of(value) {
+                    Some(pc) => {
+                        self.breakpoints.insert(pc);
+                        eprintln!("breakpoint {}", self.location(pc));
+                    }
+                    None => eprintln!("unknown address: {value}"),
+                },
+            },
+            "d" | "delete" => match arg.and_then(|value| self.symbols.pc_of(value)) {
+                Some(pc) if self.breakpoints.remove(&pc) => (),
+                _ => eprintln!("no such breakpoint"),
+            },
+            "w" | "watch" => match arg {
+                None => {
+                    for &bit in &self.watchpoints {
+                        eprintln!("watchpoint {}", self.bit_name(bit));
+                    }
+                }
+                Some(value) => match self.symbols.bit_of(value) {
+                    Some(bit) => {
+                        self.watchpoints.insert(bit);
+                        eprintln!("watchpoint {}", self.bit_name(bit));
+                    }
+                    None => eprintln!("unknown bit: {value}"),
+                },
+            },
+            "u" | "unwatch" => match arg.and_then(|value| self.symbols.bit_of(value)) {
+                Some(bit) if self.watchpoints.remove(&bit) => (),
+                _ => eprintln!("no such watchpoint"),
+            },
+            "x" | "mem" => {
+                let from = arg.and_then(|value| self.symbols.bit_of(value));
+                let to = token.get(2).and_then(|value| self.symbols.bit_of(value));
+                match (arg, from, to) {
+                    (None, ..) => self.dump(0x00, 0x7f),
+                    (Some(_), Some(from), None) if token.len() == 2 => self.dump(from, from),
+                    (Some(_), Some(from), Some(to)) if from <= to => self.dump(from, to),
+                    _ => eprintln!("usage: mem [FROM [TO]]"),
+                }
+            }
+            "set" => {
+                let bit = arg.and_then(|value| self.symbols.bit_of(value));
+                match (bit, token.get(2).copied()) {
+                    (Some(bit), Some(value @ ("0" | "1"))) if bit < 0xfd => {
+                        self.vcpu.set_bit(bit, value == "1");
+                    }
+                    _ => eprintln!("usage: set BIT 0|1 (RAM bit)"),
+                }
+            }
+            "bt" | "stack" => {
+                eprintln!("#0 {}", self.location(self.vcpu.pc));
+                for (i, &pc) in self.vcpu.pc_save.iter().rev().enumerate() {
+                    eprintln!("#{} {}", i + 1, self.location(pc));
+                }
+            }
+            "i" | "input" => {
+                let bits = token[1..].concat();
+                if bits.is_empty() || !bits.chars().all(|c| c == '0' || c == '1') {
+                    eprintln!("usage: input BITS");
+                } else {
+                    self.vcpu.stdin_queue.extend(bits.chars().map(|c| c == '1'));
+                    eprintln!("{} stdin bits queued", self.vcpu.stdin_queue.len());
+                }
+            }
+            "p" | "pc" => self.show_pc(),
+            "q" | "quit" => return false,
+            "h" | "help" => eprintln!("{HELP}"),
+            _ => eprintln!("unknown command: {cmd} (help: h)"),
+        }
+        true
+    }
+
+    pub fn repl(&mut self) {
+        self.show_pc();
+        let mut last = String::new();
+        loop {
+            eprint!("(bitcpu) ");
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+                break;
+            }
+            if line.trim().is_empty() {
+                line = last.clone();
+            }
+            if !self.command(&line) {
+                break;
+            }
+            last = line;
+        }
+        io::stdout().flush().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcpu::{CpuType, Instr, Profile, Ret, Stack};
+    use crate::io::Buffer;
+
+    const SYM: &str = "label sub 0x0003\nequ carry 0x10\n";
+
+    fn vcpu(prog: Vec<Instr>) -> Vcpu<Buffer<bool>> {
+        let io = Buffer::default();
+        Vcpu::new(
+            CpuType::Nand,
+            prog,
+            Profile::Call,
+            Ret::Zero,
+            Stack::Unbounded,
+            io,
+        )
+    }
+
+    // call sub, carry = 1, jmp end, sub: 0x11 = 1, ret
+    fn prog() -> Vec<Instr> {
+        vec![
+            (0xfc, 0x00, 0x03),
+            (0x10, 0xfe, 0xfe),
+            (0xff, 0x00, 0x05),
+            (0x11, 0xfe, 0xfe),
+            (0xfc, 0x00, 0x00),
+        ]
+    }
+
+    #[test]
+    fn next_and_finish() {
+        let (mut vcpu, symbols) = (vcpu(prog()), Symbols::parse(SYM));
+        let mut debugger = Debugger::new(&mut vcpu, &symbols);
+        debugger.command("n");
+        assert_eq!(debugger.vcpu.pc, 1);
+        assert!(debugger.vcpu.data[0x11] && debugger.vcpu.pc_save.is_empty());
+        debugger.command("rs 3"); // ret, 0x11 = 1, call
+        assert_eq!(debugger.vcpu.pc, 0);
+        debugger.command("s");
+        assert_eq!((debugger.vcpu.pc, debugger.vcpu.pc_save.len()), (3, 1));
+        debugger.command("finish");
+        assert_eq!((debugger.vcpu.pc, debugger.vcpu.pc_save.len()), (1, 0));
+        assert!(!debugger.command("q"));
+    }
+
+    #[test]
+    fn breakpoint() {
+        let (mut vcpu, symbols) = (vcpu(prog()), Symbols::parse(SYM));
+        let mut debugger = Debugger::new(&mut vcpu, &symbols);
+        debugger.command("b sub");
+        debugger.command("c");
+        assert_eq!(debugger.vcpu.pc, 3);
+        debugger.command("d sub");
+        debugger.command("c");
+        assert_eq!(debugger.vcpu.pc, 5);
+        assert_eq!(debugger.vcpu.stats.steps, 5);
+    }
+
+    #[test]
+    fn watchpoint() {
+        let (mut vcpu, symbols) = (vcpu(prog()), Symbols::parse(SYM));
+        let mut debugger = Debugger::new(&mut vcpu, &symbols);
+        debugger.command("w carry");
+        debugger.command("c");
+        assert_eq!(debugger.vcpu.pc, 2);
+        assert!(debugger.vcpu.data[0x10]);
+        debugger.command("rc");
+        assert_eq!(debugger.vcpu.pc, 1);
+        assert!(!debugger.vcpu.data[0x10]);
+        debugger.command("u carry");
+        debugger.command("c");
+        assert_eq!(debugger.vcpu.pc, 5);
+    }
+
+    #[test]
+    fn set_bit() {
+        let (mut vcpu, symbols) = (vcpu(prog()), Symbols::parse(SYM));
+        let mut debugger = Debugger::new(&mut vcpu, &symbols);
+        debugger.command("set carry 1");
+        debugger.command("set 0xfd 1"); // not a RAM bit
+        assert!(debugger.vcpu.data[0x10] && !debugger.vcpu.data[0xfd]);
+    }
+
+    #[test]
+    fn queued_input() {
+        // 0x10 = nand(stdin, stdin)
+        let (mut vcpu, symbols) = (vcpu(vec![(0x10, 0xfd, 0xfd)]), Symbols::default());
+        let mut debugger = Debugger::new(&mut vcpu, &symbols);
+        debugger.queued_input = true;
+        debugger.command("s");
+        assert_eq!(debugger.vcpu.pc, 0); // waits for stdin
+        debugger.command("input 0");
+        debugger.command("s");
+        debugger.command("s");
+        assert_eq!(debugger.vcpu.pc, 0);
+        debugger.command("i 1");
+        debugger.command("s");
+        assert_eq!(debugger.vcpu.pc, 1);
+        assert!(debugger.vcpu.data[0x10]);
+    }
+}
@@ -0,0 +1,542 @@
+// GDB remote serial protocol stub of the bit CPU
+//
+//   bitcpu-call <file> --gdb 1234    (gdb) target remote localhost:1234
+//   bitcpu-call <file> --gdb stdio   (gdb) target remote | bitcpu-call <file> --gdb stdio
+//
+// Registers (32 bit): pc, depth (call stack size), ret0..ret7 (return
+// addresses, ret0: top of the call stack)
+// Memory: 0x00..0xff, one byte (0 or 1) per bit of the data array
+// Breakpoints (Z0, Z1), write watchpoints (Z2), step, continue, ctrl-c,
+// reverse step and continue (bs, bc) with replayed stdin bits.
+// CPU faults stop with SIGSEGV, the faulting instruction is not executed.
+// stdio: the program output is sent as console output, the program input
+// must be queued with "monitor input BITS".
+
+use crate::bitcpu::Vcpu;
+use crate::io::{BitIo, BitText, Buffer, Stream};
+use crate::{Cpu, CpuFault};
+use std::collections::BTreeSet;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+const STACK_REGS: usize = 8;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>bitcpu</architecture>
+  <feature name="org.bitcpu.core">
+    <reg name="pc" bitsize="32" type="code_ptr" regnum="0"/>
+    <reg name="depth" bitsize="32" type="uint32"/>
+    <reg name="ret0" bitsize="32" type="code_ptr"/>
+    <reg name="ret1" bitsize="32" type="code_ptr"/>
+    <reg name="ret2" bitsize="32" type="code_ptr"/>
+    <reg name="ret3" bitsize="32" type="code_ptr"/>
+    <reg name="ret4" bitsize="32" type="code_ptr"/>
+    <reg name="ret5" bitsize="32" type="code_ptr"/>
+    <reg name="ret6" bitsize="32" type="code_ptr"/>
+    <reg name="ret7" bitsize="32" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |sum, b| sum.wrapping_add(b))
+}
+
+fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_hex(value: &str) -> Option<usize> {
+    usize::from_str_radix(value, 16).ok()
+}
+
+// register value, target byte order (little endian)
+fn reg_hex(value: usize) -> String {
+    hex_encode(&(value as u32).to_le_bytes())
+}
+
+fn reg_value(hex: &str) -> Option<usize> {
+    let bytes = hex_decode(hex)?.try_into().ok()?;
+    Some(u32::from_le_bytes(bytes) as usize)
+}
+
+enum Stop {
+    Trap,
+    Watch(u8),
+    Exited,
+    HistoryStart,
+    Fault(CpuFault),
+}
+
+pub struct Stub<'a, I: BitIo = Stream> {
+    vcpu: &'a mut Vcpu<I>,
+    reader: Box<dyn Read + 'a>,
+    writer: Box<dyn Write + 'a>,
+    tcp: Option<TcpStream>, // for ctrl-c while running
+    stdio: bool,
+    output: BitText, // captured program output
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<u8>,
+}
+
+impl<'a, I: BitIo> Stub<'a, I> {
+    // target: TCP port on localhost or "stdio"
+    pub fn new(vcpu: &'a mut Vcpu<I>, target: &str) -> Self {
+        if target == "stdio" {
+            return Stub::open(vcpu, Box::new(io::stdin()), Box::new(io::stdout()), None);
+        }
+        let listener = TcpListener::bind(format!("127.0.0.1:{target}")).unwrap_or_else(|err| {
+            eprintln!("gdb: cannot listen on port {target}: {err}");
+            std::process::exit(-1);
+        });
+        eprintln!("gdb: waiting for connection on 127.0.0.1:{target}");
+        let (stream, addr) = listener.accept().expect("gdb: accept failed");
+        eprintln!("gdb: connected from {addr}");
+        let reader = stream.try_clone().expect("gdb: socket clone failed");
+        let poll = stream.try_clone().expect("gdb: socket clone failed");
+        Stub::open(
+            vcpu,
+            Box::new(BufReader::new(reader)),
+            Box::new(stream),
+            Some(poll),
+        )
+    }
+
+    // without tcp: the packets share the channel with the program I/O, the
+    // program output is sent as console output
+    fn open(
+        vcpu: &'a mut Vcpu<I>,
+        reader: Box<dyn Read + 'a>,
+        writer: Box<dyn Write + 'a>,
+        tcp: Option<TcpStream>,
+    ) -> Self {
+        if tcp.is_none() {
+            vcpu.stdout_capture = Some(Buffer::default());
+        }
+        vcpu.record();
+        Stub {
+            vcpu,
+            reader,
+            writer,
+            stdio: tcp.is_none(),
+            tcp,
+            output: BitText::default(),
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+        }
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut byte = [0; 1];
+        self.reader.read_exact(&mut byte).ok()?;
+        Some(byte[0])
+    }
+
+    // packet data without "$", "#" and checksum, None: connection closed
+    // (a packet with a bad checksum is nacked and sent again)
+    fn read_packet(&mut self) -> Option<String> {
+        loop {
+            loop {
+                match self.read_byte()? {
+                    b'$' => break,
+                    0x03 => return Some("?".to_string()), // ctrl-c while stopped
+                    _ => (),                              // ack, nack
+                }
+            }
+            let mut data = vec![];
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    byte => data.push(byte),
+                }
+            }
+            let sum = [self.read_byte()?, self.read_byte()?];
+            let data = String::from_utf8_lossy(&data).into_owned();
+            let valid = std::str::from_utf8(&sum)
+                .ok()
+                .and_then(|sum| u8::from_str_radix(sum, 16).ok())
+                .is_some_and(|sum| sum == checksum(&data));
+            self.writer
+                .write_all(if valid { b"+" } else { b"-" })
+                .ok()?;
+            self.writer.flush().ok()?;
+            if valid {
+                return Some(data);
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) {
+        let packet = format!("${data}#{:02x}", checksum(data));
+        self.writer.write_all(packet.as_bytes()).unwrap();
+        self.writer.flush().unwrap();
+    }
+
+    // console output of the debugger
+    fn console(&mut self, text: &str) {
+        self.send(&format!("O{}", hex_encode(text.as_bytes())));
+    }
+
+    fn flush_output(&mut self) {
+        if let Some(capture) = &mut self.vcpu.stdout_capture {
+            let bits = std::mem::take(&mut capture.output);
+            let text: String = bits
+                .into_iter()
+                .map(|bit| self.output.format(bit))
+                .collect();
+            if !text.is_empty() {
+                self.console(&text);
+            }
+        } else {
+            self.vcpu.io.flush();
+        }
+    }
+
+    fn interrupted(&mut self) -> bool {
+        let Some(tcp) = &self.tcp else {
+            return false;
+        };
+        let mut byte = [0; 1];
+        tcp.set_nonblocking(true).unwrap();
+        let ctrl_c = matches!(tcp.peek(&mut byte), Ok(1) if byte[0] == 0x03);
+        if ctrl_c {
+            let _ = (&*tcp).read_exact(&mut byte);
+        }
+        tcp.set_nonblocking(false).unwrap();
+        ctrl_c
+    }
+
+    // stdio: no stdin for the program, the next instruction needs queued bits
+    fn starving(&self) -> bool {
+        self.stdio && self.vcpu.stdin_available() < self.vcpu.stdin_reads(self.vcpu.pc)
+    }
+
+    fn resume(&mut self, single: bool) -> Stop {
+        let mut count: u64 = 0;
+        loop {
+            let pc = self.vcpu.pc;
+            if pc >= self.vcpu.prog.len() {
+                return Stop::Exited;
+            }
+            if self.starving() {
+                self.flush_output();
+                self.console("program waits for stdin: monitor input BITS\n");
+                return Stop::Trap;
+            }
+            let dst = self.vcpu.effective(self.vcpu.prog[pc].0);
+            if !self.vcpu.step() {
+                if let Some(fault) = self.vcpu.fault {
+                    return Stop::Fault(fault);
+                }
+            }
+            count += 1;
+            if self.watchpoints.contains(&dst) {
+                return Stop::Watch(dst);
+            }
+            if single || self.breakpoints.contains(&self.vcpu.pc) {
+                return Stop::Trap;
+            }
+            if count.is_multiple_of(4096) && self.interrupted() {
+                return Stop::Trap;
+            }
+        }
+    }
+
+    fn reverse(&mut self, single: bool) -> Stop {
+        loop {
+            if !self.vcpu.unstep() {
+                return Stop::HistoryStart;
+            }
+            let dst = self.vcpu.effective(self.vcpu.prog[self.vcpu.pc].0);
+            if self.watchpoints.contains(&dst) {
+                return Stop::Watch(dst);
+            }
+            if single || self.breakpoints.contains(&self.vcpu.pc) {
+                return Stop::Trap;
+            }
+        }
+    }
+
+    fn stop_reply(&mut self, stop: Stop) -> String {
+        self.flush_output();
+        match stop {
+            Stop::Trap => "S05".to_string(),
+            Stop::Watch(bit) => format!("T05watch:{bit:x};"),
+            Stop::Exited => "W00".to_string(),
+            Stop::HistoryStart => "T05replaylog:begin;".to_string(),
+            Stop::Fault(fault) => {
+                self.console(&format!("fault: {}\n", fault.message()));
+                "S0b".to_string() // SIGSEGV
+            }
+        }
+    }
+
+    fn register(&self, n: usize) -> Option<usize> {
+        let stack = &self.vcpu.pc_save;
+        match n {
+            0 => Some(self.vcpu.pc),
+            1 => Some(stack.len()),
+            _ if n < 2 + STACK_REGS => Some(stack.iter().rev().nth(n - 2).copied().unwrap_or(0)),
+            _ => None,
+        }
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        let Some((addr, len)) = args.split_once(',') else {
+            return "E01".to_string();
+        };
+        let (Some(addr), Some(len)) = (parse_hex(addr), parse_hex(len)) else {
+            return "E01".to_string();
+        };
+        let Some(end) = addr.checked_add(len) else {
+            return "E01".to_string();
+        };
+        if addr >= self.vcpu.data.len() {
+            return "E01".to_string();
+        }
+        let end = end.min(self.vcpu.data.len());
+        let bits: Vec<u8> = self.vcpu.data[addr..end].iter().map(|&b| b as u8).collect();
+        hex_encode(&bits)
+    }
+
+    // RAM only, the I/O addresses 0xfd..0xff are not written
+    fn write_memory(&mut self, args: &str) -> String {
+        let Some((range, data)) = args.split_once(':') else {
+            return "E01".to_string();
+        };
+        let Some((addr, _)) = range.split_once(',') else {
+            return "E01".to_string();
+        };
+        let (Some(addr), Some(data)) = (parse_hex(addr), hex_decode(data)) else {
+            return "E01".to_string();
+        };
+        if addr.checked_add(data.len()).is_none_or(|end| end > 0xfd) {
+            return "E01".to_string();
+        }
+        for (i, byte) in data.into_iter().enumerate() {
+            self.vcpu.set_bit((addr + i) as u8, byte != 0);
+        }
+        "OK".to_string()
+    }
+
+    // Z/z: breakpoint (0, 1) or write watchpoint (2)
+    fn set_point(&mut self, args: &str, insert: bool) -> String {
+        let token: Vec<&str> = args.split(',').collect();
+        let Some(addr) = token.get(1).and_then(|addr| parse_hex(addr)) else {
+            return "E01".to_string();
+        };
+        match token[0] {
+            "0" | "1" if insert => self.breakpoints.insert(addr),
+            "0" | "1" => self.breakpoints.remove(&addr),
+            "2" if addr < 0xfd && insert => self.watchpoints.insert(addr as u8),
+            "2" if addr < 0xfd => self.watchpoints.remove(&(addr as u8)),
+            _ => return String::new(),
+        };
+        "OK".to_string()
+    }
+
+    fn monitor(&mut self, hex: &str) -> String {
+        let Some(cmd) = hex_decode(hex) else {
+            return "E01".to_string();
+        };
+        let cmd = String::from_utf8_lossy(&cmd).into_owned();
+        let token: Vec<&str> = cmd.split_whitespace().collect();
+        match token.first().copied() {
+            Some("input") => {
+                let bits = token[1..].concat();
+                if bits.is_empty() || !bits.chars().all(|c| c == '0' || c == '1') {
+                    return "E01".to_string();
+                }
+                self.vcpu.stdin_queue.extend(bits.chars().map(|c| c == '1'));
+                let msg = format!("{} stdin bits queued\n", self.vcpu.stdin_queue.len());
+                hex_encode(msg.as_bytes())
+            }
+            Some("reset") => {
+                self.vcpu.reset();
+                "OK".to_string()
+            }
+            _ => hex_encode(b"monitor commands: input BITS, reset\n"),
+        }
+    }
+
+    fn features(&self, args: &str) -> String {
+        let Some((offset, len)) = args.split_once(',') else {
+            return "E01".to_string();
+        };
+        let (Some(offset), Some(len)) = (parse_hex(offset), parse_hex(len)) else {
+            return "E01".to_string();
+        };
+        let Some(end) = offset.checked_add(len) else {
+            return "E01".to_string();
+        };
+        if offset >= TARGET_XML.len() {
+            return "l".to_string();
+        }
+        let end = end.min(TARGET_XML.len());
+        let more = if end < TARGET_XML.len() { "m" } else { "l" };
+        format!("{more}{}", &TARGET_XML[offset..end])
+    }
+
+    pub fn serve(&mut self) {
+        while let Some(packet) = self.read_packet() {
+            let (cmd, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+            let reply = match cmd {
+                "?" => "S05".to_string(),
+                "g" => (0..2 + STACK_REGS)
+                    .map(|n| reg_hex(self.register(n).unwrap()))
+                    .collect(),
+                "G" => match reg_value(args.get(..8).unwrap_or("")) {
+                    Some(pc) => {
+                        self.vcpu.set_pc(pc);
+                        "OK".to_string()
+                    }
+                    None => "E01".to_string(),
+                },
+                "p" => match parse_hex(args).and_then(|n| self.register(n)) {
+                    Some(value) => reg_hex(value),
+                    None => "E01".to_string(),
+                },
+                "P" => match args.split_once('=') {
+                    Some(("0", value)) => match reg_value(value) {
+                        Some(pc) => {
+                            self.vcpu.set_pc(pc);
+                            "OK".to_string()
+                        }
+                        _ => "E01".to_string(),
+                    },
+                    _ => "E01".to_string(), // call stack: read only
+                },
+                "m" => self.read_memory(args),
+                "M" => self.write_memory(args),
+                "s" | "c" => {
+                    if let Some(pc) = parse_hex(args) {
+                        self.vcpu.set_pc(pc);
+                    }
+                    let stop = self.resume(cmd == "s");
+                    self.stop_reply(stop)
+                }
+                "b" if args == "s" || args == "c" => {
+                    let stop = self.reverse(args == "s");
+                    self.stop_reply(stop)
+                }
+                "Z" => self.set_point(args, true),
+                "z" => self.set_point(args, false),
+                "H" => "OK".to_string(),
+                "k" => break,
+                "D" => {
+                    self.send("OK");
+                    break;
+                }
+                "q" if args.starts_with("Supported") => {
+                    "PacketSize=4000;qXfer:features:read+;ReverseStep+;ReverseContinue+".to_string()
+                }
+                "q" if args.starts_with("Xfer:features:read:target.xml:") => {
+                    self.features(&args["Xfer:features:read:target.xml:".len()..])
+                }
+                "q" if args.starts_with("Rcmd,") => self.monitor(&args["Rcmd,".len()..]),
+                "q" if args == "Attached" => "1".to_string(),
+                "q" if args == "C" => "QC1".to_string(),
+                "q" if args == "fThreadInfo" => "m1".to_string(),
+                "q" if args == "sThreadInfo" => "l".to_string(),
+                _ => String::new(), // not supported
+            };
+            self.send(&reply);
+        }
+        self.flush_output();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcpu::{CpuType, Profile, Ret, Stack};
+
+    fn packet(data: &str) -> String {
+        format!("${data}#{:02x}", checksum(data))
+    }
+
+    // replies to the packets: stdout 0, RAM 0x00 = 1, RAM 0x01 = 0
+    fn session(input: &str) -> Vec<String> {
+        let prog = vec![(0xfd, 0xff, 0xff), (0x00, 0xfe, 0xfe), (0x01, 0x00, 0x00)];
+        let io = Buffer::default();
+        let mut vcpu = Vcpu::new(
+            CpuType::Nand,
+            prog,
+            Profile::Call,
+            Ret::Zero,
+            Stack::Unbounded,
+            io,
+        );
+        let mut output = vec![];
+        Stub::open(
+            &mut vcpu,
+            Box::new(input.as_bytes()),
+            Box::new(&mut output),
+            None,
+        )
+        .serve();
+        let output = String::from_utf8(output).unwrap();
+        output
+            .split('$')
+            .skip(1)
+            .map(|reply| reply.split('#').next().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn step_and_reverse() {
+        let packets: String = ["?", "g", "m0,2", "Z0,2,1", "c", "m0,2", "bs", "p0", "k"]
+            .iter()
+            .map(|data| packet(data))
+            .collect();
+        let zero = "00000000".repeat(STACK_REGS + 1);
+        let expected = [
+            "S05",
+            &format!("00000000{zero}"),
+            "0000",
+            "OK",
+            &format!("O{}", hex_encode(b"0")),
+            "S05",
+            "0100",
+            "S05",
+            "01000000",
+        ];
+        assert_eq!(session(&packets), expected);
+    }
+
+    #[test]
+    fn reverse_after_write() {
+        let packets: String = ["Z0,2,1", "c", "M0,1:00", "bc", "m0,2", "k"]
+            .iter()
+            .map(|data| packet(data))
+            .collect();
+        let console = format!("O{}", hex_encode(b"0"));
+        let expected = ["OK", &console, "S05", "OK", "T05replaylog:begin;", "0000"];
+        assert_eq!(session(&packets), expected);
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let input = "$?#00".to_string() + &packet("?") + &packet("k");
+        assert_eq!(session(&input), ["S05"]);
+    }
+
+    #[test]
+    fn target_xml() {
+        let packets = packet("qXfer:features:read:target.xml:0,ffff") + &packet("k");
+        let reply = session(&packets).remove(0);
+        assert!(reply.starts_with('l') && reply.contains("<architecture>bitcpu</architecture>"));
+    }
+}
@@ -0,0 +1,391 @@
+// I/O devices of the emulators
+//
+// BitIo:  bit CPU stdin/stdout (read and write of 0xfd)
+// WordIo: subleq stdin/stdout (read 0xfe, write 0xff)
+// Stream: process stdin/stdout or files, bits as ASCII '0'/'1' or packed
+//         into bytes, words as characters
+// Buffer: in-memory input and output, e.g. embedded emulators
+// Script: input and the expected output, e.g. tests
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+pub trait BitIo {
+    // None: end of input
+    fn read_bit(&mut self) -> Option<bool>;
+
+    fn write_bit(&mut self, bit: bool);
+
+    fn flush(&mut self) {}
+}
+
+pub trait WordIo {
+    // None: end of input
+    fn read_word(&mut self) -> Option<i16>;
+
+    // false: the value can not be written, e.g. not a character
+    fn write_word(&mut self, value: i16) -> bool;
+
+    fn flush(&mut self) {}
+}
+
+// '0'/'1' text of output bits, a space after 4 bits, two after 8 bits
+#[derive(Default)]
+pub(crate) struct BitText {
+    count: u8,
+}
+
+impl BitText {
+    pub(crate) fn format(&mut self, bit: bool) -> String {
+        let mut text = ((0x30 + bit as u8) as char).to_string(); // inverse
+        self.count += 1;
+        match self.count {
+            4 => text.push(' '),
+            8 => {
+                text.push_str("  ");
+                self.count = 0;
+            }
+            _ => (),
+        }
+        text
+    }
+}
+
+// bits of a Stream
+// text:     ASCII '0'/'1', other input characters are skipped
+// msb, lsb: 8 bits per byte, most or least significant bit first,
+//           an incomplete last output byte is not written
+#[derive(Clone, Copy, PartialEq)]
+pub enum BitFormat {
+    Text,
+    Msb,
+    Lsb,
+}
+
+impl BitFormat {
+    pub fn parse(value: &str) -> Option<BitFormat> {
+        match value.to_lowercase().as_str() {
+            "text" => Some(BitFormat::Text),
+            "msb" => Some(BitFormat::Msb),
+            "lsb" => Some(BitFormat::Lsb),
+            _ => None,
+        }
+    }
+
+    // shift of the n-th bit (0..8) of a byte
+    fn shift(&self, n: u8) -> u8 {
+        match self {
+            BitFormat::Lsb => n,
+            _ => 7 - n,
+        }
+    }
+}
+
+pub struct Stream {
+    input: Box<dyn Read>,
+    output: Box<dyn Write>,
+    pub format: BitFormat,
+    text: BitText,
+    input_byte: (u8, u8),  // byte, bits read
+    output_byte: (u8, u8), // byte, bits written
+}
+
+impl Stream {
+    pub fn new(input: Box<dyn Read>, output: Box<dyn Write>) -> Self {
+        Stream {
+            input,
+            output,
+            format: BitFormat::Text,
+            text: BitText::default(),
+            input_byte: (0, 8),
+            output_byte: (0, 0),
+        }
+    }
+
+    // process stdin/stdout
+    pub fn stdio() -> Self {
+        Stream::new(Box::new(io::stdin()), Box::new(io::stdout()))
+    }
+
+    // files instead of stdin/stdout
+    pub fn open(input: Option<&str>, output: Option<&str>) -> io::Result<Self> {
+        let input: Box<dyn Read> = match input {
+            Some(fname) => Box::new(BufReader::new(File::open(fname)?)),
+            None => Box::new(io::stdin()),
+        };
+        let output: Box<dyn Write> = match output {
+            Some(fname) => Box::new(BufWriter::new(File::create(fname)?)),
+            None => Box::new(io::stdout()),
+        };
+        Ok(Stream::new(input, output))
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut inp: [u8; 1] = [0; 1];
+        self.input.read_exact(&mut inp).ok()?;
+        Some(inp[0])
+    }
+}
+
+impl BitIo for Stream {
+    fn read_bit(&mut self) -> Option<bool> {
+        if self.format == BitFormat::Text {
+            loop {
+                let byte = self.read_byte()?;
+                if byte == b'0' || byte == b'1' {
+                    return Some(byte == b'1');
+                }
+            }
+        }
+        if self.input_byte.1 == 8 {
+            self.input_byte = (self.read_byte()?, 0);
+        }
+        let (byte, n) = self.input_byte;
+        self.input_byte.1 += 1;
+        Some(byte >> self.format.shift(n) & 1 != 0)
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        if self.format == BitFormat::Text {
+            let text = self.text.format(bit);
+            self.output.write_all(text.as_bytes()).unwrap();
+            return;
+        }
+        let (byte, n) = self.output_byte;
+        self.output_byte = (byte | (bit as u8) << self.format.shift(n), n + 1);
+        if self.output_byte.1 == 8 {
+            self.output.write_all(&[self.output_byte.0]).unwrap();
+            self.output_byte = (0, 0);
+        }
+    }
+
+    fn flush(&mut self) {
+        self.output.flush().unwrap();
+    }
+}
+
+impl WordIo for Stream {
+    fn read_word(&mut self) -> Option<i16> {
+        self.read_byte().map(|byte| byte as i16)
+    }
+
+    fn write_word(&mut self, value: i16) -> bool {
+        let Some(c) = u32::try_from(value).ok().and_then(char::from_u32) else {
+            return false;
+        };
+        write!(self.output, "{c}").unwrap();
+        true
+    }
+
+    fn flush(&mut self) {
+        self.output.flush().unwrap();
+    }
+}
+
+#[derive(Default)]
+pub struct Buffer<T> {
+    pub input: VecDeque<T>,
+    pub output: Vec<T>,
+}
+
+impl<T> Buffer<T> {
+    pub fn new(input: impl IntoIterator<Item = T>) -> Self {
+        Buffer {
+            input: input.into_iter().collect(),
+            output: vec![],
+        }
+    }
+}
+
+impl BitIo for Buffer<bool> {
+    fn read_bit(&mut self) -> Option<bool> {
+        self.input.pop_front()
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        self.output.push(bit);
+    }
+}
+
+impl WordIo for Buffer<i16> {
+    fn read_word(&mut self) -> Option<i16> {
+        self.input.pop_front()
+    }
+
+    fn write_word(&mut self, value: i16) -> bool {
+        self.output.push(value);
+        true
+    }
+}
+
+pub struct Script<T> {
+    pub buffer: Buffer<T>,
+    pub expected: Vec<T>,
+}
+
+impl<T: PartialEq> Script<T> {
+    pub fn new(input: impl IntoIterator<Item = T>, expected: impl IntoIterator<Item = T>) -> Self {
+        Script {
+            buffer: Buffer::new(input),
+            expected: expected.into_iter().collect(),
+        }
+    }
+
+    // position of the first difference to the expected output, also a
+    // missing or an additional value
+    pub fn mismatch(&self) -> Option<usize> {
+        let output = &self.buffer.output;
+        let same = output
+            .iter()
+            .zip(&self.expected)
+            .take_while(|(a, b)| a == b)
+            .count();
+        (same < output.len().max(self.expected.len())).then_some(same)
+    }
+}
+
+impl BitIo for Script<bool> {
+    fn read_bit(&mut self) -> Option<bool> {
+        self.buffer.read_bit()
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        self.buffer.write_bit(bit);
+    }
+}
+
+impl WordIo for Script<i16> {
+    fn read_word(&mut self) -> Option<i16> {
+        self.buffer.read_word()
+    }
+
+    fn write_word(&mut self, value: i16) -> bool {
+        self.buffer.write_word(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcpu::{self, Profile, Ret, Stack};
+    use crate::{subleq, CpuFault, Halt};
+
+    // value as count bits, most significant bit first
+    fn bits(value: u8, count: u8) -> Vec<bool> {
+        (0..count).rev().map(|n| value >> n & 1 != 0).collect()
+    }
+
+    fn add_4bit(io: Script<bool>) -> (Halt, Script<bool>) {
+        let src = include_str!("../../bitcpu-base/sample/add_4bit.nand");
+        let (cputype, _, prog) = bitcpu::compiler(src).unwrap();
+        let mut vcpu = bitcpu::Vcpu::new(
+            cputype,
+            prog,
+            Profile::Base,
+            Ret::Zero,
+            Stack::Unbounded,
+            io,
+        );
+        (vcpu.runner(), vcpu.io)
+    }
+
+    #[test]
+    fn add_4bit_script() {
+        for (a, b) in [(0, 0), (6, 11), (9, 5), (15, 15)] {
+            let input = [bits(a, 4), bits(b, 4)].concat();
+            let (halt, script) = add_4bit(Script::new(input, bits(a + b, 8)));
+            assert!(matches!(halt, Halt::End));
+            assert_eq!(script.mismatch(), None, "{a} + {b}");
+        }
+    }
+
+    #[test]
+    fn script_mismatch() {
+        let input = [bits(6, 4), bits(11, 4)].concat();
+        let (_, script) = add_4bit(Script::new(input.clone(), bits(16, 8)));
+        assert_eq!(script.mismatch(), Some(7)); // 0001 0001, expected 0001 0000
+        let (_, script) = add_4bit(Script::new(input, bits(17, 4)));
+        assert_eq!(script.mismatch(), Some(4)); // additional output
+    }
+
+    #[test]
+    fn add_4bit_end_of_input() {
+        let (halt, _) = add_4bit(Script::new(bits(6, 4), []));
+        assert!(matches!(
+            halt,
+            Halt::Fault {
+                fault: CpuFault::InputEof,
+                ..
+            }
+        ));
+    }
+
+    // bits read from the bytes
+    fn read_bits(input: &[u8], format: BitFormat) -> Vec<bool> {
+        let mut stream = Stream::new(
+            Box::new(io::Cursor::new(input.to_vec())),
+            Box::new(io::sink()),
+        );
+        stream.format = format;
+        std::iter::from_fn(|| stream.read_bit()).collect()
+    }
+
+    // bytes written for the bits
+    fn write_bits(bits: &[bool], format: BitFormat) -> Vec<u8> {
+        let name = format!("vcpu-test-{}-{}.out", std::process::id(), format as u8);
+        let fname = std::env::temp_dir().join(name);
+        let fname = fname.to_str().unwrap();
+        let mut stream = Stream::open(None, Some(fname)).unwrap();
+        stream.format = format;
+        for &bit in bits {
+            stream.write_bit(bit);
+        }
+        BitIo::flush(&mut stream);
+        let output = std::fs::read(fname).unwrap();
+        std::fs::remove_file(fname).unwrap();
+        output
+    }
+
+    #[test]
+    fn bit_format() {
+        assert!(BitFormat::parse("MSB") == Some(BitFormat::Msb));
+        assert!(BitFormat::parse("lsb") == Some(BitFormat::Lsb));
+        assert!(BitFormat::parse("text") == Some(BitFormat::Text));
+        assert!(BitFormat::parse("ascii").is_none());
+    }
+
+    #[test]
+    fn read_bytes() {
+        // 'A' = 0x41 = 0100 0001
+        assert_eq!(read_bits(b"A", BitFormat::Msb), bits(0x41, 8));
+        let mut lsb = bits(0x41, 8);
+        lsb.reverse();
+        assert_eq!(read_bits(b"A", BitFormat::Lsb), lsb);
+        assert_eq!(read_bits(b"AB", BitFormat::Msb).len(), 16);
+        // text: other characters are skipped
+        assert_eq!(read_bits(b"0 1x1\n", BitFormat::Text), [false, true, true]);
+    }
+
+    #[test]
+    fn write_bytes() {
+        let mut bits_a = bits(0x41, 8);
+        assert_eq!(write_bits(&bits_a, BitFormat::Msb), b"A");
+        bits_a.reverse();
+        assert_eq!(write_bits(&bits_a, BitFormat::Lsb), b"A");
+        // an incomplete last byte is not written
+        let input = [bits(0x42, 8), bits(0x0f, 4)].concat();
+        assert_eq!(write_bits(&input, BitFormat::Msb), b"B");
+        assert_eq!(write_bits(&bits(0x41, 8), BitFormat::Text), b"0100 0001  ");
+    }
+
+    #[test]
+    fn subleq_buffer() {
+        let src = include_str!("../../subleq/sample/HelloWorld.subleq");
+        let (cputype, prog, rom) = subleq::compiler(src);
+        let mut vcpu = subleq::Vcpu::new(cputype, prog, rom, Buffer::default());
+        assert!(matches!(vcpu.runner(), Halt::End));
+        let text: String = vcpu.io.output.iter().map(|&c| c as u8 as char).collect();
+        assert!(!text.is_empty() && text.lines().all(|line| line == "Hello World!"));
+    }
+}
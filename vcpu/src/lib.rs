@@ -0,0 +1,103 @@
+// Minimal instruction CPU emulators
+//
+// bitcpu: one bit logical instruction (nand, nor, xor, xnor), base and call profile
+// subleq: one u32 instruction (subleq, addleq)
+
+pub mod bitcpu;
+pub mod cli;
+pub mod coverage;
+pub mod debug;
+pub mod gdb;
+pub mod io;
+pub mod profile;
+pub mod subleq;
+pub mod symbols;
+pub mod trace;
+pub mod vcd;
+
+// Why the runner stopped
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Halt {
+    End,      // pc after the last instruction
+    MaxSteps, // instruction budget used up
+    // machine state repeated without I/O: pc, loop address range
+    Loop {
+        pc: usize,
+        first: usize,
+        last: usize,
+    },
+    // program error: pc and number of the faulting instruction
+    Fault {
+        fault: CpuFault,
+        pc: usize,
+        step: u64,
+    },
+}
+
+// Program errors detected by the emulators
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CpuFault {
+    StackUnderflow, // ret with an empty call stack
+    StackOverflow,  // call with a full call stack (fixed depth)
+    PcOutOfRange,   // jump target before the first instruction
+    RomOverflow,    // ROM larger than its address range
+    InvalidOutput,  // stdout value is not a character
+    InputEof,       // stdin closed
+    Unmapped,       // access to an address without RAM or peripheral
+}
+
+impl CpuFault {
+    pub fn message(&self) -> &'static str {
+        match self {
+            CpuFault::StackUnderflow => "ret with an empty call stack",
+            CpuFault::StackOverflow => "call with a full call stack",
+            CpuFault::PcOutOfRange => "jump target out of range",
+            CpuFault::RomOverflow => "ROM overflow",
+            CpuFault::InvalidOutput => "invalid output character",
+            CpuFault::InputEof => "end of stdin",
+            CpuFault::Unmapped => "access to an unmapped address",
+        }
+    }
+}
+
+// Run statistics, stdin/stdout: bits (bitcpu) or characters (subleq)
+#[derive(Clone, Default)]
+pub struct Stats {
+    pub steps: u64,
+    pub jumps: u64,
+    pub calls: u64,
+    pub rets: u64,
+    pub skips: u64,
+    pub stdin: u64,
+    pub stdout: u64,
+}
+
+impl Stats {
+    pub fn report(&self, unit: &str) -> String {
+        format!(
+            "instructions: {}\njumps: {}\ncalls: {}\nrets: {}\nskips: {}\nstdin {unit}: {}\nstdout {unit}: {}",
+            self.steps, self.jumps, self.calls, self.rets, self.skips, self.stdin, self.stdout
+        )
+    }
+}
+
+// Common interface of the emulators, for tests, debuggers and profilers
+pub trait Cpu {
+    // memory cell: bool (bitcpu) or i16 (subleq)
+    type Word: Copy;
+
+    // Execute one instruction, false: the program is finished or faulted
+    fn step(&mut self) -> bool;
+
+    // Run until the program is finished
+    fn run(&mut self) {
+        while self.step() {}
+    }
+
+    fn pc(&self) -> usize;
+
+    fn memory(&self) -> &[Self::Word];
+
+    // Initial state: pc 0, cleared RAM (ROM loaded)
+    fn reset(&mut self);
+}
@@ -0,0 +1,291 @@
+// Profiler of the bit CPU: executions per instruction address, aggregated
+// per subroutine (call/ret, shadow call stack) and per label (symbol file)
+//
+// Report on stderr at exit, sorted by count:
+// subroutines: self (own instructions) and total (with called subroutines)
+//
+// Flamegraph: folded stacks ("main;full_adder;half_adder 42"), weighted by
+// executed instructions, for flamegraph.pl or inferno-flamegraph
+
+use crate::bitcpu::Vcpu;
+use crate::io::BitIo;
+use crate::symbols::Symbols;
+use crate::trace::{Step, Tracer};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+const HOT_ADDRESSES: usize = 20;
+
+fn percent(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 * 100.0 / total as f64
+    }
+}
+
+// name of a subroutine by the entry address, entry 0: main
+fn subroutine_name(symbols: &Symbols, entry: Option<usize>) -> String {
+    match entry {
+        None => "main".to_string(),
+        Some(addr) => match symbols.label_at(addr) {
+            Some(label) => label.to_string(),
+            None => format!("0x{addr:04x}"),
+        },
+    }
+}
+
+pub struct Profiler {
+    symbols: Symbols,
+    counts: Vec<u64>,                                 // per pc
+    stack: Vec<usize>,                                // entries of the called subroutines
+    subroutines: BTreeMap<Option<usize>, (u64, u64)>, // entry: (self, total)
+}
+
+impl Profiler {
+    pub fn new(symbols: &Symbols) -> Self {
+        Profiler {
+            symbols: symbols.clone(),
+            counts: vec![],
+            stack: vec![],
+            subroutines: BTreeMap::new(),
+        }
+    }
+
+    fn report<I: BitIo>(&self, vcpu: &Vcpu<I>) -> String {
+        let total: u64 = self.counts.iter().sum();
+        let mut report = format!("profile: {total} instructions\n");
+
+        let mut subroutines: Vec<(&Option<usize>, &(u64, u64))> = self.subroutines.iter().collect();
+        subroutines.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then(a.0.cmp(b.0)));
+        report.push_str("\n    self       %     total       %  subroutine\n");
+        for (&entry, &(own, all)) in subroutines {
+            report.push_str(&format!(
+                "{own:8} {:6.2}% {all:9} {:6.2}%  {}\n",
+                percent(own, total),
+                percent(all, total),
+                subroutine_name(&self.symbols, entry)
+            ));
+        }
+
+        let mut labels: BTreeMap<&str, u64> = BTreeMap::new();
+        for (pc, &count) in self.counts.iter().enumerate() {
+            if count > 0 {
+                *labels
+                    .entry(self.symbols.label_of(pc).unwrap_or("-"))
+                    .or_default() += count;
+            }
+        }
+        if labels.keys().any(|&label| label != "-") {
+            let mut labels: Vec<(&str, u64)> = labels.into_iter().collect();
+            labels.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+            report.push_str("\n   count       %  label\n");
+            for (label, count) in labels {
+                report.push_str(&format!(
+                    "{count:8} {:6.2}%  {label}\n",
+                    percent(count, total)
+                ));
+            }
+        }
+
+        let mut hot: Vec<(usize, u64)> = self
+            .counts
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(pc, &count)| (pc, count))
+            .collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        report.push_str("\n   count       %  address\n");
+        for &(pc, count) in hot.iter().take(HOT_ADDRESSES) {
+            let location = match self.symbols.location(pc) {
+                Some(label) => format!("{pc:04x} <{label}>"),
+                None => format!("{pc:04x}"),
+            };
+            report.push_str(&format!(
+                "{count:8} {:6.2}%  {location}: {}\n",
+                percent(count, total),
+                vcpu.disasm(pc)
+            ));
+        }
+        report
+    }
+}
+
+impl<I: BitIo> Tracer<I> for Profiler {
+    fn step(&mut self, _vcpu: &Vcpu<I>, step: &Step) {
+        if self.counts.len() <= step.pc {
+            self.counts.resize(step.pc + 1, 0);
+        }
+        self.counts[step.pc] += 1;
+        let current = self.stack.last().copied();
+        self.subroutines.entry(current).or_default().0 += 1;
+        self.subroutines.entry(None).or_default().1 += 1;
+        for (i, &entry) in self.stack.iter().enumerate() {
+            if !self.stack[..i].contains(&entry) {
+                self.subroutines.entry(Some(entry)).or_default().1 += 1;
+            }
+        }
+        if step.call {
+            self.stack.push(step.next_pc);
+        }
+        if step.ret {
+            self.stack.pop();
+        }
+    }
+
+    fn finish(&mut self, vcpu: &Vcpu<I>) {
+        io::stdout().flush().unwrap();
+        eprint!("{}", self.report(vcpu));
+    }
+}
+
+pub struct Flamegraph {
+    fname: String,
+    symbols: Symbols,
+    stack: Vec<usize>,                 // entries of the called subroutines
+    folded: BTreeMap<Vec<usize>, u64>, // call stack: instructions
+}
+
+impl Flamegraph {
+    pub fn new(fname: &str, symbols: &Symbols) -> Self {
+        Flamegraph {
+            fname: fname.to_string(),
+            symbols: symbols.clone(),
+            stack: vec![],
+            folded: BTreeMap::new(),
+        }
+    }
+}
+
+impl<I: BitIo> Tracer<I> for Flamegraph {
+    fn step(&mut self, _vcpu: &Vcpu<I>, step: &Step) {
+        match self.folded.get_mut(&self.stack) {
+            Some(count) => *count += 1,
+            None => {
+                self.folded.insert(self.stack.clone(), 1);
+            }
+        }
+        if step.call {
+            self.stack.push(step.next_pc);
+        }
+        if step.ret {
+            self.stack.pop();
+        }
+    }
+
+    fn finish(&mut self, _vcpu: &Vcpu<I>) {
+        let file = File::create(&self.fname).unwrap_or_else(|err| {
+            eprintln!("{}: {err}", self.fname);
+            std::process::exit(-1);
+        });
+        let mut out = BufWriter::new(file);
+        for (stack, count) in &self.folded {
+            let mut names = vec![subroutine_name(&self.symbols, None)];
+            names.extend(
+                stack
+                    .iter()
+                    .map(|&entry| subroutine_name(&self.symbols, Some(entry))),
+            );
+            writeln!(out, "{} {count}", names.join(";")).unwrap();
+        }
+        out.flush().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcpu::{CpuType, Instr, Profile, Ret, Stack};
+    use crate::io::Buffer;
+    use std::fs;
+
+    const SYM: &str = "label main 0x0000\nlabel sub 0x0003\n";
+
+    // main: call sub, 0x10 = 1, 0x11 = 1, sub: 0x12 = 1, ret
+    fn vcpu() -> Vcpu<Buffer<bool>> {
+        let prog: Vec<Instr> = vec![
+            (0xfc, 0x00, 0x03),
+            (0x10, 0xfe, 0xfe),
+            (0x11, 0xfe, 0xfe),
+            (0x12, 0xfe, 0xfe),
+            (0xfc, 0x00, 0x00),
+        ];
+        Vcpu::new(
+            CpuType::Nand,
+            prog,
+            Profile::Call,
+            Ret::Zero,
+            Stack::Unbounded,
+            Buffer::default(),
+        )
+    }
+
+    // executed instructions of the program: pc, call, ret, next pc
+    fn steps() -> Vec<Step> {
+        let steps = [
+            (0, true, false, 3),
+            (3, false, false, 4),
+            (4, false, true, 1),
+            (1, false, false, 2),
+            (2, false, false, 3),
+        ];
+        steps
+            .into_iter()
+            .enumerate()
+            .map(|(i, (pc, call, ret, next_pc))| Step {
+                step: i as u64 + 1,
+                pc,
+                instr: (0x10, 0xfe, 0xfe),
+                write: 0x10,
+                src: (false, false),
+                old: false,
+                new: true,
+                result: true,
+                skip: false,
+                jump: false,
+                call,
+                ret,
+                next_pc,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn profiler() {
+        let vcpu = vcpu();
+        let mut profiler = Profiler::new(&Symbols::parse(SYM));
+        for step in steps() {
+            profiler.step(&vcpu, &step);
+        }
+        let report = profiler.report(&vcpu);
+        assert!(report.starts_with("profile: 5 instructions\n"));
+        assert!(report.contains("       3  60.00%         5 100.00%  main\n"));
+        assert!(report.contains("       2  40.00%         2  40.00%  sub\n"));
+        assert!(report.contains("\n       3  60.00%  main\n       2  40.00%  sub\n"));
+        assert!(report.contains("       1  20.00%  0004 <sub+1>: ret\n"));
+    }
+
+    // folded stacks of the program
+    fn folded(symbols: &Symbols) -> String {
+        let vcpu = vcpu();
+        let fname = std::env::temp_dir().join(format!("vcpu-test-{}.folded", std::process::id()));
+        let fname = fname.to_str().unwrap();
+        let mut flamegraph = Flamegraph::new(fname, symbols);
+        for step in steps() {
+            flamegraph.step(&vcpu, &step);
+        }
+        flamegraph.finish(&vcpu);
+        let folded = fs::read_to_string(fname).unwrap();
+        fs::remove_file(fname).unwrap();
+        folded
+    }
+
+    #[test]
+    fn flamegraph() {
+        assert_eq!(folded(&Symbols::parse(SYM)), "main 3\nmain;sub 2\n");
+        // without a label: the entry address
+        assert_eq!(folded(&Symbols::default()), "main 3\nmain;0x0003 2\n");
+    }
+}
@@ -0,0 +1,301 @@
+use crate::io::{Stream, WordIo};
+use crate::{Cpu, CpuFault, Halt, Stats};
+use std::collections::VecDeque;
+
+pub enum CpuType {
+    Addleq,
+    Subleq,
+}
+// addrA addrB jmpaddr
+pub type Instr = (u8, u8, i16);
+
+fn parser(value: &str) -> i16 {
+    if value.starts_with("0x") {
+        i16::from_str_radix(value.strip_prefix("0x").unwrap(), 16).unwrap()
+    } else if value.starts_with("-0x") {
+        -i16::from_str_radix(value.strip_prefix("-0x").unwrap(), 16).unwrap()
+    } else {
+        value.parse().unwrap()
+    }
+}
+
+pub fn compiler(src: &str) -> (CpuType, Vec<Instr>, Vec<i16>) {
+    let mut cputype = CpuType::Subleq;
+    let mut prog = vec![];
+    let mut rom = vec![];
+    for (i, line) in src.lines().enumerate() {
+        let rowstart = line.split('#').next().unwrap().trim();
+        if rowstart.len() < 5 {
+            continue;
+        }
+        if i == 0 {
+            cputype = match rowstart {
+                "ADDLEQ" => CpuType::Addleq,
+                "SUBLEQ" => CpuType::Subleq,
+                _ => {
+                    eprintln!("First line: ADDLEQ or SUBLEQ");
+                    std::process::exit(-1);
+                }
+            };
+            continue;
+        }
+        let mut token = rowstart.split_whitespace();
+        let first = token.next().unwrap();
+        if first == "rom" {
+            for x in token {
+                rom.push(parser(x));
+            }
+        } else {
+            let addr_a = parser(first) as u8;
+            let addr_b = parser(token.next().unwrap()) as u8;
+            let jmpaddr = parser(token.next().unwrap());
+            prog.push((addr_a, addr_b, jmpaddr));
+        }
+    }
+    (cputype, prog, rom)
+}
+
+// -- VCPU Runner --
+
+// ROM 0x80..0xfd, 0xfe stdin
+const ROM_SIZE: usize = 126;
+
+pub struct Vcpu<I: WordIo = Stream> {
+    cputype: CpuType,
+    prog: Vec<Instr>,
+    rom: Vec<i16>,
+    pc: usize,
+    data: [i16; 256],
+    pub io: I,                  // stdin/stdout device
+    stdin_queue: VecDeque<i16>, // read before stdin: undone reads
+    fault: Option<CpuFault>,    // why the last step failed
+    pub max_steps: Option<u64>,
+    pub stats: Stats,
+}
+
+impl<I: WordIo> Vcpu<I> {
+    // Memory & memory mapped functions
+    fn mem_rd(&mut self, addr: u8) -> i16 {
+        match addr {
+            0x00..=0xfd => self.data[addr as usize], // RAM, ROM
+            0xfe => match self.stdin_queue.pop_front().or_else(|| self.io.read_word()) {
+                // Stdin
+                Some(value) => {
+                    self.stats.stdin += 1;
+                    value
+                }
+                None => {
+                    self.fault = Some(CpuFault::InputEof);
+                    0
+                }
+            },
+            0xff => 0, // Stdout, read 0
+        }
+    }
+
+    // stdin value of a faulting instruction, read again by the next try
+    fn unread(&mut self, addr: u8, value: i16) {
+        if addr == 0xfe {
+            self.stdin_queue.push_front(value);
+            self.stats.stdin -= 1;
+        }
+    }
+
+    // Memory & memory mapped functions
+    fn mem_wr(&mut self, addr: u8, value: i16) {
+        match addr {
+            0x00..=0x7f => self.data[addr as usize] = value, // RAM, last: readable stdout
+            0x80..=0xfe => (),                               // ROM write not allowed
+            0xff => {
+                // Stdout
+                if self.io.write_word(value) {
+                    self.stats.stdout += 1;
+                } else {
+                    self.fault = Some(CpuFault::InvalidOutput);
+                }
+            }
+        }
+    }
+
+    pub fn new(cputype: CpuType, prog: Vec<Instr>, rom: Vec<i16>, io: I) -> Self {
+        let data: [i16; 256] = [0; 256];
+        let mut vcpu = Vcpu {
+            cputype,
+            prog,
+            rom,
+            pc: 0,
+            data,
+            io,
+            stdin_queue: VecDeque::new(),
+            fault: None,
+            max_steps: None,
+            stats: Stats::default(),
+        };
+        vcpu.reset();
+        vcpu
+    }
+
+    pub fn runner(&mut self) -> Halt {
+        let halt = self.run_program();
+        self.io.flush();
+        halt
+    }
+
+    fn run_program(&mut self) -> Halt {
+        // CPU run
+        if self.rom.len() > ROM_SIZE {
+            return Halt::Fault {
+                fault: CpuFault::RomOverflow,
+                pc: self.pc,
+                step: 0,
+            };
+        }
+        loop {
+            if self.pc >= self.prog.len() {
+                return Halt::End;
+            }
+            if self.max_steps.is_some_and(|max| self.stats.steps >= max) {
+                return Halt::MaxSteps;
+            }
+            if !self.step() {
+                if let Some(fault) = self.fault {
+                    return Halt::Fault {
+                        fault,
+                        pc: self.pc,
+                        step: self.stats.steps + 1,
+                    };
+                }
+            }
+        }
+    }
+}
+
+impl<I: WordIo> Cpu for Vcpu<I> {
+    type Word = i16;
+
+    fn step(&mut self) -> bool {
+        if self.pc >= self.prog.len() {
+            return false;
+        }
+        let instr = self.prog[self.pc];
+        // faults: the instruction is not counted, pc stays, the stdin
+        // operands are undone, no stdin character is lost
+        self.fault = None;
+        let a = self.mem_rd(instr.0);
+        if self.fault.is_some() {
+            return false;
+        }
+        let b = self.mem_rd(instr.1);
+        if self.fault.is_some() {
+            self.unread(instr.0, a);
+            return false;
+        }
+        let result = match self.cputype {
+            CpuType::Addleq => a.wrapping_add(b),
+            CpuType::Subleq => a.wrapping_sub(b),
+        };
+        let target = if result <= 0 {
+            self.pc as isize + 1 + instr.2 as isize
+        } else {
+            self.pc as isize + 1
+        };
+        if target < 0 {
+            self.fault = Some(CpuFault::PcOutOfRange);
+        } else {
+            self.mem_wr(instr.0, result);
+        }
+        if self.fault.is_some() {
+            self.unread(instr.1, b);
+            self.unread(instr.0, a);
+            return false;
+        }
+        self.stats.steps += 1;
+        if result <= 0 {
+            self.stats.jumps += 1;
+        }
+        self.pc = target as usize;
+        true
+    }
+
+    fn run(&mut self) {
+        self.runner();
+    }
+
+    fn pc(&self) -> usize {
+        self.pc
+    }
+
+    fn memory(&self) -> &[i16] {
+        &self.data
+    }
+
+    fn reset(&mut self) {
+        self.pc = 0;
+        self.data = [0; 256];
+        let rom = &self.rom[..self.rom.len().min(ROM_SIZE)];
+        self.data[0x80..0x80 + rom.len()].copy_from_slice(rom);
+        self.stdin_queue.clear();
+        self.fault = None;
+        self.stats = Stats::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::Buffer;
+
+    fn run(src: &str, input: &[i16]) -> (Halt, Vcpu<Buffer<i16>>) {
+        let (cputype, prog, rom) = compiler(src);
+        let mut vcpu = Vcpu::new(cputype, prog, rom, Buffer::new(input.to_vec()));
+        (vcpu.runner(), vcpu)
+    }
+
+    #[test]
+    fn max_steps() {
+        // 0 - 0 = 0: jump to itself
+        let (cputype, prog, rom) = compiler("SUBLEQ\n0x00 0x00 -1\n");
+        let mut vcpu = Vcpu::new(cputype, prog, rom, Buffer::default());
+        vcpu.max_steps = Some(7);
+        assert!(matches!(vcpu.runner(), Halt::MaxSteps));
+        assert_eq!((vcpu.stats.steps, vcpu.stats.jumps), (7, 7));
+    }
+
+    #[test]
+    fn stats() {
+        // stdout = 0 - 3, stdin 5 - 2 = 3 > 0
+        let (halt, vcpu) = run("SUBLEQ\nrom 3 2\n0xff 0x80 0\n0xfe 0x81 0\n", &[5]);
+        assert!(matches!(halt, Halt::End));
+        assert_eq!(vcpu.io.output, vec![-3]);
+        let stats = &vcpu.stats;
+        assert_eq!(
+            (stats.steps, stats.jumps, stats.stdin, stats.stdout),
+            (2, 1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn negative_branch_not_taken() {
+        // 0 - (-1) = 1 > 0: no jump
+        let (halt, vcpu) = run("SUBLEQ\nrom -1 2\n0x00 0x80 -5\n", &[]);
+        assert!(matches!(halt, Halt::End));
+        assert_eq!(vcpu.data[0], 1);
+    }
+
+    #[test]
+    fn negative_branch_keeps_stdin() {
+        // stdin 1 - 2 = -1 <= 0: jump before the first instruction
+        let (halt, vcpu) = run("SUBLEQ\nrom -1 2\n0xfe 0x81 -5\n", &[1, 7]);
+        assert!(matches!(
+            halt,
+            Halt::Fault {
+                fault: CpuFault::PcOutOfRange,
+                pc: 0,
+                step: 1
+            }
+        ));
+        assert_eq!(vcpu.stdin_queue, [1]);
+        assert_eq!(vcpu.io.input, [7]);
+        assert_eq!(vcpu.stats.stdin, 0);
+    }
+}
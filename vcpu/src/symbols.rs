@@ -0,0 +1,172 @@
+// Symbol file of the assembler (<basename>.sym)
+//
+// label <name> <pc>
+// equ <name> <RAM bit>
+// line <pc> <line> <file>   (source map)
+
+use std::collections::BTreeMap;
+use std::fs;
+
+#[derive(Clone, Default)]
+pub struct Symbols {
+    labels: BTreeMap<usize, String>,
+    bits: BTreeMap<u8, String>,
+    lines: BTreeMap<usize, (usize, String)>,
+}
+
+// hex (0x..) or decimal number, e.g. an address
+pub(crate) fn parse_number(value: &str) -> Option<usize> {
+    match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+impl Symbols {
+    pub fn parse(src: &str) -> Self {
+        let mut symbols = Symbols::default();
+        for line in src.lines() {
+            let rowstart = line.split('#').next().unwrap().trim();
+            if let Some(source) = rowstart.strip_prefix("line ") {
+                let token: Vec<&str> = source.trim().splitn(3, ' ').collect();
+                if let [pc, linenum, file] = token[..] {
+                    if let (Some(pc), Ok(linenum)) = (parse_number(pc), linenum.parse()) {
+                        symbols.lines.insert(pc, (linenum, file.to_string()));
+                    }
+                }
+                continue;
+            }
+            let token: Vec<&str> = rowstart.split_whitespace().collect();
+            let [kind, name, value] = token[..] else {
+                continue;
+            };
+            let Some(value) = parse_number(value) else {
+                continue;
+            };
+            match kind {
+                "label" => {
+                    symbols.labels.insert(value, name.to_string());
+                }
+                // first name of the bit wins, e.g. stdin (stdout)
+                "equ" if value < 0x100 => {
+                    symbols.bits.entry(value as u8).or_insert(name.to_string());
+                }
+                _ => (),
+            }
+        }
+        symbols
+    }
+
+    pub fn load(fname: &str) -> Self {
+        let src = fs::read_to_string(fname).expect("symbol file not found");
+        Symbols::parse(&src)
+    }
+
+    pub fn label_addr(&self, name: &str) -> Option<usize> {
+        self.labels
+            .iter()
+            .find(|(_, label)| label.as_str() == name)
+            .map(|(&addr, _)| addr)
+    }
+
+    pub fn bit_addr(&self, name: &str) -> Option<u8> {
+        self.bits
+            .iter()
+            .find(|(_, bit)| bit.as_str() == name)
+            .map(|(&addr, _)| addr)
+    }
+
+    pub fn bit_name(&self, addr: u8) -> Option<&str> {
+        self.bits.get(&addr).map(|name| name.as_str())
+    }
+
+    // nearest label at or before the pc
+    pub fn label_of(&self, pc: usize) -> Option<&str> {
+        self.labels
+            .range(..=pc)
+            .next_back()
+            .map(|(_, name)| name.as_str())
+    }
+
+    pub fn label_at(&self, pc: usize) -> Option<&str> {
+        self.labels.get(&pc).map(|name| name.as_str())
+    }
+
+    // (file, line) of the instruction
+    pub fn source(&self, pc: usize) -> Option<(&str, usize)> {
+        self.lines
+            .get(&pc)
+            .map(|(linenum, file)| (file.as_str(), *linenum))
+    }
+
+    // label of the pc, or the nearest label before: "loop+2"
+    pub fn location(&self, pc: usize) -> Option<String> {
+        self.labels
+            .range(..=pc)
+            .next_back()
+            .map(|(&addr, name)| match pc - addr {
+                0 => name.clone(),
+                offset => format!("{name}+{offset}"),
+            })
+    }
+
+    // address (0x.. or decimal) or symbol name
+    pub fn pc_of(&self, value: &str) -> Option<usize> {
+        parse_number(value).or_else(|| self.label_addr(value))
+    }
+
+    pub fn bit_of(&self, value: &str) -> Option<u8> {
+        match parse_number(value) {
+            Some(addr) => u8::try_from(addr).ok(),
+            None => self.bit_addr(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYM: &str = "\
+label start 0x0000
+label loop 0x0004  # comment
+equ stdin 0xfd
+equ stdout 0xfd
+equ carry 0x10
+line 0x0004 12 src/my prog.asm
+label bad 0xzz
+";
+
+    #[test]
+    fn parse() {
+        let symbols = Symbols::parse(SYM);
+        assert_eq!(symbols.label_addr("loop"), Some(4));
+        assert_eq!(symbols.label_addr("bad"), None);
+        assert_eq!(symbols.bit_addr("carry"), Some(0x10));
+        assert_eq!(symbols.bit_name(0xfd), Some("stdin")); // first name wins
+        assert_eq!(symbols.source(4), Some(("src/my prog.asm", 12)));
+        assert_eq!(symbols.source(5), None);
+    }
+
+    #[test]
+    fn location() {
+        let symbols = Symbols::parse(SYM);
+        assert_eq!(symbols.location(0).as_deref(), Some("start"));
+        assert_eq!(symbols.location(6).as_deref(), Some("loop+2"));
+        assert_eq!(symbols.label_of(3), Some("start"));
+        assert_eq!(symbols.label_at(3), None);
+        assert_eq!(Symbols::default().location(6), None);
+    }
+
+    #[test]
+    fn addresses() {
+        let symbols = Symbols::parse(SYM);
+        assert_eq!(symbols.pc_of("loop"), Some(4));
+        assert_eq!(symbols.pc_of("0x10"), Some(0x10));
+        assert_eq!(symbols.pc_of("17"), Some(17));
+        assert_eq!(symbols.bit_of("carry"), Some(0x10));
+        assert_eq!(symbols.bit_of("0xfe"), Some(0xfe));
+        assert_eq!(symbols.bit_of("0x100"), None);
+        assert_eq!(symbols.bit_of("unknown"), None);
+    }
+}
@@ -0,0 +1,145 @@
+// Trace sinks of the bit CPU: called after every executed instruction
+
+use crate::bitcpu::{Instr, Vcpu};
+use crate::io::{BitIo, Stream};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+// One executed instruction
+pub struct Step {
+    pub step: u64, // step number, first instruction: 1
+    pub pc: usize,
+    pub instr: Instr,
+    pub write: u8,         // written address: dst, index window: the selected RAM bit
+    pub src: (bool, bool), // operand values, 0xfd: stdin bit
+    pub old: bool,         // written RAM bit before the instruction
+    pub new: bool,         // written RAM bit after, a device cell keeps its bit
+    pub result: bool,      // written value, dst 0xfd: stdout bit
+    pub skip: bool,
+    pub jump: bool,
+    pub call: bool,
+    pub ret: bool,
+    pub next_pc: usize,
+}
+
+pub trait Tracer<I: BitIo = Stream> {
+    fn step(&mut self, vcpu: &Vcpu<I>, step: &Step);
+
+    // end of the run
+    fn finish(&mut self, _vcpu: &Vcpu<I>) {}
+}
+
+// JSON lines trace, one object per executed instruction:
+// {"step":1,"pc":0,"instr":[255,0,23],"op":"jmp 0x0017","src":[1,1],
+//  "result":0,"skip":false,"jump":true,"call":false,"ret":false,"next_pc":23}
+// memory delta (option): "mem":{"addr":16,"old":0,"new":1} if a RAM bit changed
+pub struct JsonLines {
+    out: BufWriter<Box<dyn Write>>,
+    mem: bool,
+}
+
+impl JsonLines {
+    // fname "-": stderr
+    pub fn create(fname: &str, mem: bool) -> Self {
+        let out: Box<dyn Write> = if fname == "-" {
+            Box::new(io::stderr())
+        } else {
+            Box::new(File::create(fname).unwrap_or_else(|err| {
+                eprintln!("{fname}: {err}");
+                std::process::exit(-1);
+            }))
+        };
+        JsonLines {
+            out: BufWriter::new(out),
+            mem,
+        }
+    }
+}
+
+impl<I: BitIo> Tracer<I> for JsonLines {
+    fn step(&mut self, vcpu: &Vcpu<I>, step: &Step) {
+        let (dst, src1, src2) = step.instr;
+        let mut line = format!(
+            "{{\"step\":{},\"pc\":{},\"instr\":[{dst},{src1},{src2}],\"op\":\"{}\",\
+             \"src\":[{},{}],\"result\":{},\"skip\":{},\"jump\":{},\"call\":{},\"ret\":{},\
+             \"next_pc\":{}",
+            step.step,
+            step.pc,
+            vcpu.disasm(step.pc),
+            step.src.0 as u8,
+            step.src.1 as u8,
+            step.result as u8,
+            step.skip,
+            step.jump,
+            step.call,
+            step.ret,
+            step.next_pc
+        );
+        if self.mem && dst < 0xfd && step.old != step.new {
+            line.push_str(&format!(
+                ",\"mem\":{{\"addr\":{},\"old\":{},\"new\":{}}}",
+                step.write, step.old as u8, step.new as u8
+            ));
+        }
+        line.push('}');
+        writeln!(self.out, "{line}").unwrap();
+    }
+
+    fn finish(&mut self, _vcpu: &Vcpu<I>) {
+        self.out.flush().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcpu::{CpuType, Profile, Ret, Stack};
+    use crate::io::Buffer;
+    use std::fs;
+
+    // JSON lines of the program, mem: memory delta
+    fn trace(prog: Vec<Instr>, mem: bool) -> Vec<String> {
+        let mut vcpu = Vcpu::new(
+            CpuType::Nand,
+            prog,
+            Profile::Call,
+            Ret::Zero,
+            Stack::Unbounded,
+            Buffer::new([true]),
+        );
+        let name = format!("vcpu-test-{}-{mem}.jsonl", std::process::id());
+        let fname = std::env::temp_dir().join(name);
+        let fname = fname.to_str().unwrap();
+        vcpu.tracers.push(Box::new(JsonLines::create(fname, mem)));
+        vcpu.runner();
+        vcpu.finish_trace();
+        let text = fs::read_to_string(fname).unwrap();
+        fs::remove_file(fname).unwrap();
+        text.lines().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn json_lines() {
+        // 0x10 = nand(stdin, 1), jmp 0x0002
+        let lines = trace(vec![(0x10, 0xfd, 0xff), (0xff, 0x00, 0x02)], false);
+        assert_eq!(
+            lines,
+            [
+                "{\"step\":1,\"pc\":0,\"instr\":[16,253,255],\"op\":\"0x10 = nand(0xfd, 0xff)\",\
+                 \"src\":[1,1],\"result\":0,\"skip\":false,\"jump\":false,\"call\":false,\
+                 \"ret\":false,\"next_pc\":1}",
+                "{\"step\":2,\"pc\":1,\"instr\":[255,0,2],\"op\":\"jmp 0x0002\",\
+                 \"src\":[0,0],\"result\":1,\"skip\":false,\"jump\":true,\"call\":false,\
+                 \"ret\":false,\"next_pc\":2}",
+            ]
+        );
+    }
+
+    #[test]
+    fn memory_delta() {
+        // 0x10 = nand(0, 0), 0x10 = nand(1, 0): unchanged
+        let lines = trace(vec![(0x10, 0xfe, 0xfe), (0x10, 0xff, 0xfe)], true);
+        assert!(lines[0].ends_with(",\"mem\":{\"addr\":16,\"old\":0,\"new\":1}}"));
+        assert!(!lines[1].contains("\"mem\""));
+    }
+}
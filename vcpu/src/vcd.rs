@@ -0,0 +1,166 @@
+// Value Change Dump (VCD) trace of the bit CPU for waveform viewers
+//
+// Signals: RAM bits 0x00..0xfc (equ names from the symbol file), pc,
+// stdin/stdout (last bit) and stdin_rd/stdout_wr events.
+// One timestep per executed instruction.
+
+use crate::bitcpu::Vcpu;
+use crate::io::BitIo;
+use crate::symbols::Symbols;
+use crate::trace::{Step, Tracer};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+const RAM_BITS: usize = 0xfd;
+const PC: usize = RAM_BITS;
+const STDIN: usize = RAM_BITS + 1;
+const STDOUT: usize = RAM_BITS + 2;
+const STDIN_RD: usize = RAM_BITS + 3;
+const STDOUT_WR: usize = RAM_BITS + 4;
+
+// short identifier of a signal: printable ASCII '!'..'~'
+fn ident(signal: usize) -> String {
+    let mut id = String::new();
+    let mut n = signal;
+    loop {
+        id.push((b'!' + (n % 94) as u8) as char);
+        n /= 94;
+        if n == 0 {
+            return id;
+        }
+        n -= 1;
+    }
+}
+
+pub struct Vcd {
+    out: BufWriter<File>,
+}
+
+impl Vcd {
+    pub fn create<I: BitIo>(fname: &str, vcpu: &Vcpu<I>, symbols: &Symbols) -> Self {
+        let file = File::create(fname).unwrap_or_else(|err| {
+            eprintln!("{fname}: {err}");
+            std::process::exit(-1);
+        });
+        let mut vcd = Vcd {
+            out: BufWriter::new(file),
+        };
+        vcd.header(vcpu, symbols);
+        vcd
+    }
+
+    fn header<I: BitIo>(&mut self, vcpu: &Vcpu<I>, symbols: &Symbols) {
+        let out = &mut self.out;
+        writeln!(out, "$version bitcpu $end").unwrap();
+        writeln!(out, "$timescale 1 ns $end").unwrap();
+        writeln!(out, "$scope module bitcpu $end").unwrap();
+        for bit in 0..RAM_BITS {
+            let name = match symbols.bit_name(bit as u8) {
+                Some(name) => name.to_string(),
+                None => format!("bit_{bit:02x}"),
+            };
+            writeln!(out, "$var wire 1 {} {name} $end", ident(bit)).unwrap();
+        }
+        writeln!(out, "$var wire 16 {} pc $end", ident(PC)).unwrap();
+        writeln!(out, "$var wire 1 {} stdin $end", ident(STDIN)).unwrap();
+        writeln!(out, "$var wire 1 {} stdout $end", ident(STDOUT)).unwrap();
+        writeln!(out, "$var event 1 {} stdin_rd $end", ident(STDIN_RD)).unwrap();
+        writeln!(out, "$var event 1 {} stdout_wr $end", ident(STDOUT_WR)).unwrap();
+        writeln!(out, "$upscope $end").unwrap();
+        writeln!(out, "$enddefinitions $end").unwrap();
+        writeln!(out, "#0").unwrap();
+        writeln!(out, "$dumpvars").unwrap();
+        for bit in 0..RAM_BITS {
+            writeln!(out, "{}{}", vcpu.data[bit] as u8, ident(bit)).unwrap();
+        }
+        writeln!(out, "b{:b} {}", vcpu.pc, ident(PC)).unwrap();
+        writeln!(out, "x{}", ident(STDIN)).unwrap();
+        writeln!(out, "x{}", ident(STDOUT)).unwrap();
+        writeln!(out, "$end").unwrap();
+    }
+}
+
+impl<I: BitIo> Tracer<I> for Vcd {
+    fn step(&mut self, vcpu: &Vcpu<I>, step: &Step) {
+        let out = &mut self.out;
+        let (dst, src1, src2) = step.instr;
+        writeln!(out, "#{}", step.step).unwrap();
+        if step.next_pc != step.pc {
+            writeln!(out, "b{:b} {}", step.next_pc, ident(PC)).unwrap();
+        }
+        // jmp, call, ret: src1, src2 are the target, stdin is not read
+        let immediate = vcpu.is_immediate(dst);
+        for (src, value) in [(src1, step.src.0), (src2, step.src.1)] {
+            if src == 0xfd && !immediate {
+                writeln!(out, "{}{}", value as u8, ident(STDIN)).unwrap();
+                writeln!(out, "1{}", ident(STDIN_RD)).unwrap();
+            }
+        }
+        match step.write as usize {
+            _ if dst == 0xfd => {
+                writeln!(out, "{}{}", step.result as u8, ident(STDOUT)).unwrap();
+                writeln!(out, "1{}", ident(STDOUT_WR)).unwrap();
+            }
+            bit if bit < RAM_BITS && step.old != step.new => {
+                writeln!(out, "{}{}", step.new as u8, ident(bit)).unwrap();
+            }
+            _ => (),
+        }
+    }
+
+    fn finish(&mut self, vcpu: &Vcpu<I>) {
+        writeln!(self.out, "#{}", vcpu.stats.steps + 1).unwrap();
+        self.out.flush().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcpu::{CpuType, Profile, Ret, Stack};
+    use crate::io::Buffer;
+    use std::fs;
+
+    #[test]
+    fn identifiers() {
+        assert_eq!(ident(0), "!");
+        assert_eq!(ident(93), "~");
+        assert_eq!(ident(94), "!!");
+        assert_eq!(ident(95), "\"!");
+        assert_eq!(ident(PC), "b\"");
+    }
+
+    #[test]
+    fn dump() {
+        // carry = nand(stdin, 1), stdout = carry, jmp 0x0003
+        let prog = vec![(0x10, 0xfd, 0xff), (0xfd, 0x10, 0xff), (0xff, 0x00, 0x03)];
+        let io = Buffer::new([false]);
+        let mut vcpu = Vcpu::new(
+            CpuType::Nand,
+            prog,
+            Profile::Call,
+            Ret::Zero,
+            Stack::Unbounded,
+            io,
+        );
+        let fname = std::env::temp_dir().join(format!("vcpu-test-{}.vcd", std::process::id()));
+        let fname = fname.to_str().unwrap();
+        let symbols = Symbols::parse("equ carry 0x10\n");
+        let vcd = Vcd::create(fname, &vcpu, &symbols);
+        vcpu.tracers.push(Box::new(vcd));
+        vcpu.runner();
+        vcpu.finish_trace();
+        let text = fs::read_to_string(fname).unwrap();
+        fs::remove_file(fname).unwrap();
+        assert!(text.contains(&format!("$var wire 1 {} carry $end", ident(0x10))));
+        let (pc, stdin, stdout) = (ident(PC), ident(STDIN), ident(STDOUT));
+        let (rd, wr, carry) = (ident(STDIN_RD), ident(STDOUT_WR), ident(0x10));
+        let changes = format!(
+            "#1\nb1 {pc}\n0{stdin}\n1{rd}\n1{carry}\n\
+             #2\nb10 {pc}\n0{stdout}\n1{wr}\n\
+             #3\nb11 {pc}\n\
+             #4\n"
+        );
+        assert!(text.ends_with(&changes), "{text}");
+    }
+}